@@ -0,0 +1,80 @@
+use crate::input::{self, Reader};
+use crate::record_batch::BatchRows;
+use crate::sym::{Interner, Sym};
+use arrow_array::RecordBatchReader;
+use arrow_ipc::reader::{FileReader, StreamReader};
+use bitvec::vec::BitVec;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+const FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// Returns whether `buf` starts with the magic bytes of an Arrow IPC file (a.k.a. Feather v2).
+pub fn is_arrow_file(buf: &[u8]) -> bool {
+    buf.starts_with(FILE_MAGIC)
+}
+
+/// Reads an Arrow IPC file, or an optionally compressed Arrow IPC stream.
+pub struct ArrowReader {
+    rdr: Box<dyn RecordBatchReader>,
+    headers: Vec<String>,
+    projection: Option<Vec<usize>>, // positions of the columns to read, set on the first read
+    rows: BatchRows,
+}
+
+impl ArrowReader {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        // stdin or a pipe can only be a stream, and sniffing it would consume its contents
+        let mut magic = [0u8; FILE_MAGIC.len()];
+        let n = match input::is_regular_file(path) {
+            true => File::open(path)?.read(&mut magic)?,
+            false => 0,
+        };
+        let rdr: Box<dyn RecordBatchReader> = if is_arrow_file(&magic[..n]) {
+            Box::new(FileReader::try_new(
+                BufReader::new(File::open(path)?),
+                None,
+            )?)
+        } else {
+            Box::new(StreamReader::try_new(input::open(path)?, None)?)
+        };
+        let headers = rdr
+            .schema()
+            .fields()
+            .iter()
+            .map(|x| x.name().trim().to_owned())
+            .collect();
+        Ok(Self {
+            rdr,
+            headers,
+            projection: None,
+            rows: BatchRows::default(),
+        })
+    }
+}
+
+impl Reader for ArrowReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        let projection = self
+            .projection
+            .get_or_insert_with(|| mask.iter_ones().collect());
+        while !self.rows.next_row(record) {
+            match self.rdr.next() {
+                // only the needed columns are formatted and interned
+                Some(batch) => self.rows = BatchRows::new(&batch?.project(projection)?, interner)?,
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
@@ -1,38 +1,272 @@
-use clap::{crate_version, Parser};
+use crate::comparator::Tolerance;
+use crate::input::InputFormat;
+use crate::threshold::Threshold;
+use clap::{crate_version, Parser, ValueEnum};
+use encoding_rs::Encoding;
 use std::path::PathBuf;
 
 /// A tabular data comparison utility
 #[derive(Parser)]
 #[clap(version=crate_version!())]
 pub struct Cli {
-    /// Key column names separated by spaces
+    /// Key column names separated by spaces, or their 1-based positions with `--no-header`
     #[clap(short, long, num_args = 1.., required = true)]
     pub key_cols: Vec<String>,
 
-    /// Path to first/left file
+    /// Path to first/left file, `-` for stdin, or a directory or glob pattern (e.g. `out/*.csv`)
+    /// of files with identical columns to be read as one
     #[clap(short, long, required = true)]
     pub left_file: PathBuf,
 
-    /// Path to second/right file
+    /// Path to second/right file, `-` for stdin, or a directory or glob pattern (e.g.
+    /// `out/*.csv`) of files with identical columns to be read as one
     #[clap(short, long, required = true)]
     pub right_file: PathBuf,
 
-    /// Delimiter used in first/left file
+    /// Add a column with this name holding the file each row was read from, shown in the report
+    /// but not compared
+    #[clap(long)]
+    pub source_column: Option<String>,
+
+    /// Format of first/left file (detected from its extension or contents if not given)
+    #[clap(long, value_enum)]
+    pub left_format: Option<InputFormat>,
+
+    /// Format of second/right file (detected from its extension or contents if not given)
+    #[clap(long, value_enum)]
+    pub right_format: Option<InputFormat>,
+
+    /// Delimiter used in first/left file, one or more characters (`\t` or `tab` for tabs)
     #[clap(long, default_value = ",", value_parser(parse_delim))]
-    pub left_delim: u8,
+    pub left_delim: String,
 
-    /// Delimiter used in second/right file
+    /// Delimiter used in second/right file, one or more characters (`\t` or `tab` for tabs)
     #[clap(long, default_value = ",", value_parser(parse_delim))]
-    pub right_delim: u8,
+    pub right_delim: String,
+
+    /// Quote character used in first/left file
+    #[clap(long, default_value = "\"", value_parser(parse_char))]
+    pub left_quote: u8,
+
+    /// Quote character used in second/right file
+    #[clap(long, default_value = "\"", value_parser(parse_char))]
+    pub right_quote: u8,
+
+    /// Character escaping quotes within quoted fields of first/left file, e.g. `\`
+    #[clap(long, value_parser(parse_char))]
+    pub left_escape: Option<u8>,
+
+    /// Character escaping quotes within quoted fields of second/right file, e.g. `\`
+    #[clap(long, value_parser(parse_char))]
+    pub right_escape: Option<u8>,
+
+    /// Do not read doubled quotes within quoted fields of first/left file as a quote
+    #[clap(long)]
+    pub left_no_double_quote: bool,
+
+    /// Do not read doubled quotes within quoted fields of second/right file as a quote
+    #[clap(long)]
+    pub right_no_double_quote: bool,
+
+    /// Skip lines of first/left file starting with this character, e.g. `#`
+    #[clap(long, value_parser(parse_char))]
+    pub left_comment: Option<u8>,
+
+    /// Skip lines of second/right file starting with this character, e.g. `#`
+    #[clap(long, value_parser(parse_char))]
+    pub right_comment: Option<u8>,
+
+    /// Number of lines of first/left file to skip before the header row
+    #[clap(long, default_value_t = 0)]
+    pub left_skip_rows: usize,
+
+    /// Number of lines of second/right file to skip before the header row
+    #[clap(long, default_value_t = 0)]
+    pub right_skip_rows: usize,
+
+    /// Encoding of first/left file if it is text, e.g. `windows-1252`, `latin1` or `utf-16le`
+    /// (UTF-8 by default, a byte order mark takes precedence)
+    #[clap(long, value_parser(parse_encoding))]
+    pub left_encoding: Option<&'static Encoding>,
+
+    /// Encoding of second/right file if it is text, e.g. `windows-1252`, `latin1` or `utf-16le`
+    /// (UTF-8 by default, a byte order mark takes precedence)
+    #[clap(long, value_parser(parse_encoding))]
+    pub right_encoding: Option<&'static Encoding>,
+
+    /// CSV files have no header row, columns are named by `--columns` or `col1`, `col2`, ...
+    #[clap(long)]
+    pub no_header: bool,
+
+    /// Column names of CSV files without a header row, e.g. `id,name,price`
+    #[clap(long, value_delimiter = ',', requires = "no_header")]
+    pub columns: Option<Vec<String>>,
+
+    /// Path to a TOML file with the column layout of first/left file, if it is fixed-width
+    #[clap(long)]
+    pub left_layout: Option<PathBuf>,
+
+    /// Path to a TOML file with the column layout of second/right file, if it is fixed-width
+    #[clap(long)]
+    pub right_layout: Option<PathBuf>,
+
+    /// Worksheet to read if first/left file is a spreadsheet (the first one by default)
+    #[clap(long)]
+    pub left_sheet: Option<String>,
+
+    /// Worksheet to read if second/right file is a spreadsheet (the first one by default)
+    #[clap(long)]
+    pub right_sheet: Option<String>,
+
+    /// Row number of the column names if first/left file is a spreadsheet, rows above it are
+    /// skipped
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub left_header_row: u32,
+
+    /// Row number of the column names if second/right file is a spreadsheet, rows above it are
+    /// skipped
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub right_header_row: u32,
+
+    /// strftime format of spreadsheet dates without a time of day
+    #[clap(long, default_value = "%Y-%m-%d")]
+    pub sheet_date_format: String,
+
+    /// strftime format of spreadsheet dates with a time of day
+    #[clap(long, default_value = "%Y-%m-%d %H:%M:%S")]
+    pub sheet_datetime_format: String,
 
-    /// Path to output html file
+    /// Number of decimals non-integer spreadsheet numbers are rounded to (shortest
+    /// representation by default)
+    #[clap(long)]
+    pub sheet_decimals: Option<usize>,
+
+    /// Number of records of JSON Lines files scanned to discover their columns (all by default),
+    /// fields only seen in later records are skipped with a warning
+    #[clap(long)]
+    pub json_sample: Option<usize>,
+
+    /// How to handle keys occurring more than once in a file
+    #[clap(long, value_enum, default_value_t = DuplicateKeys::Error)]
+    pub on_duplicate_keys: DuplicateKeys,
+
+    /// How to handle records of delimited text files with more or fewer fields than the header
+    #[clap(long, value_enum, default_value_t = Malformed::Error)]
+    pub on_malformed: Malformed,
+
+    /// Values read as nulls in the non-key columns of both files, e.g. `--null-tokens '' NULL '\N'
+    /// NA nan`, so that they are equal to each other and to missing values
+    #[clap(long, num_args = 1..)]
+    pub null_tokens: Vec<String>,
+
+    /// Compare a column numerically, e.g. `price=abs:0.01`, `qty=rel:0.001` or
+    /// `price=abs:0.01,rel:0.001` (use `price=abs:0` for exact numeric equality)
+    #[clap(long, num_args = 1.., value_parser(parse_tolerance))]
+    pub tolerance: Vec<(String, Tolerance)>,
+
+    /// Path to a TOML file with per-column comparison rules
+    #[clap(long)]
+    pub rules: Option<PathBuf>,
+
+    /// Path to output file, or `-` for stdout
     #[clap(short, long, required = true)]
     pub out_file: PathBuf,
+
+    /// Do not open the HTML report in a web browser (it is never opened in non-interactive
+    /// sessions)
+    #[clap(long)]
+    pub no_open: bool,
+
+    /// Format of the output file
+    #[clap(short, long, value_enum, default_value_t = Format::Html)]
+    pub format: Format,
+
+    /// Exit with status 1 only if this condition holds, e.g. `diff_rows>0` or
+    /// `left_only_rows>=10` (by default any difference does)
+    #[clap(long, num_args = 1.., value_parser(Threshold::parse))]
+    pub fail_if: Vec<Threshold>,
+
+    /// Exit with status 1 only if more than this percentage of cells differ
+    #[clap(long)]
+    pub max_diff_cell_pct: Option<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Minimal HTML report, opened in a web browser
+    Html,
+    /// Machine-readable JSON report
+    Json,
+    /// CSV with one row per differing cell
+    Csv,
+    /// Colored side-by-side diff for the terminal
+    Term,
 }
 
-fn parse_delim(x: &str) -> Result<u8, &'static str> {
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DuplicateKeys {
+    /// Exit with an error
+    Error,
+    /// Compare only the first row of each key
+    First,
+    /// Compare the rows of each key as a multiset, pairing identical rows first
+    Multiset,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Malformed {
+    /// Exit with an error
+    Error,
+    /// Skip the record, listing it in the report
+    Skip,
+    /// Fill in missing fields with nulls and drop extra fields, listing the record in the report
+    Pad,
+}
+
+fn parse_delim(x: &str) -> Result<String, &'static str> {
+    match x {
+        "" => Err("delimiter cannot be empty"),
+        "\\t" | "tab" => Ok("\t".to_owned()),
+        _ => Ok(x.to_owned()),
+    }
+}
+
+fn parse_char(x: &str) -> Result<u8, &'static str> {
     match x.len() {
         1 => Ok(x.as_bytes()[0]),
-        _ => Err("delimiter can only be a single ASCII character"),
+        _ => Err("should be a single ASCII character"),
+    }
+}
+
+fn parse_encoding(x: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(x.trim().as_bytes()).ok_or_else(|| format!("unknown encoding: {}", x))
+}
+
+fn parse_tolerance(x: &str) -> Result<(String, Tolerance), String> {
+    let (col, spec) = x
+        .rsplit_once('=')
+        .ok_or("tolerance should be of the form COLUMN=abs:X,rel:Y")?;
+    let mut tolerance = Tolerance::default();
+    for part in spec.split(',') {
+        let (kind, value) = part
+            .split_once(':')
+            .ok_or_else(|| format!("invalid tolerance: {}", part))?;
+        let value = value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .ok_or_else(|| format!("invalid tolerance value: {}", value))?;
+        match kind.trim() {
+            "abs" => tolerance.abs = value,
+            "rel" => tolerance.rel = value,
+            _ => {
+                return Err(format!(
+                    "unknown tolerance kind (expected abs or rel): {}",
+                    kind
+                ))
+            }
+        }
     }
+    Ok((col.trim().to_owned(), tolerance))
 }
@@ -0,0 +1,96 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+/// How the values of a non-key column are compared when they are not identical.
+///
+/// Columns without a comparator are compared exactly, i.e. values are equal only if their strings
+/// are identical.
+pub enum Comparator {
+    CaseInsensitive,
+    Numeric(Tolerance),
+    /// Values are parsed as dates or timestamps using the first matching format.
+    Date(Vec<String>),
+    /// Matches of the regex are replaced with the replacement string before comparing.
+    Regex(Regex, String),
+}
+
+impl Comparator {
+    pub fn equal(&self, a: &str, b: &str) -> bool {
+        match self {
+            Comparator::CaseInsensitive => a.to_lowercase() == b.to_lowercase(),
+            Comparator::Numeric(tolerance) => tolerance.equal(a, b),
+            Comparator::Date(formats) => match (parse_date(a, formats), parse_date(b, formats)) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+            Comparator::Regex(re, replacement) => {
+                re.replace_all(a, replacement.as_str()) == re.replace_all(b, replacement.as_str())
+            }
+        }
+    }
+}
+
+/// Numeric tolerance for comparing the values of a column.
+///
+/// Two values are considered equal if both parse as numbers and their difference is within either
+/// the absolute or the relative (to the larger magnitude of both) tolerance. With both tolerances
+/// at zero, values are equal only if they are numerically equal (e.g. `1.0` and `1.00`).
+#[derive(Clone, Copy, Default)]
+pub struct Tolerance {
+    pub abs: f64,
+    pub rel: f64,
+}
+
+impl Tolerance {
+    pub fn equal(&self, a: &str, b: &str) -> bool {
+        match (parse_number(a), parse_number(b)) {
+            (Some(a), Some(b)) => {
+                let delta = (a - b).abs();
+                delta <= self.abs || delta <= self.rel * a.abs().max(b.abs())
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_number(x: &str) -> Option<f64> {
+    x.trim().parse::<f64>().ok().filter(|x| x.is_finite())
+}
+
+// number of digits after the decimal point, ignoring numbers in scientific notation
+fn decimals(x: &str) -> usize {
+    let x = x.trim();
+    if x.contains(['e', 'E']) {
+        return 0;
+    }
+    x.split_once('.').map_or(0, |(_, frac)| frac.len())
+}
+
+/// Difference between the right value `b` and the left value `a`, if both are numbers.
+///
+/// The difference is formatted with as many decimals as the more precise value, so that it does
+/// not show floating point noise (e.g. `+0.2` instead of `0.19999999999999996`).
+pub fn numeric_delta(a: &str, b: &str) -> Option<String> {
+    let (x, y) = (parse_number(a)?, parse_number(b)?);
+    Some(format!("{:+.*}", decimals(a).max(decimals(b)), y - x))
+}
+
+/// Formats tried by the date comparator when none are configured.
+pub const DEFAULT_DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f%:z",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d",
+];
+
+// timestamps with an offset are converted to UTC, dates are taken at midnight
+fn parse_date(x: &str, formats: &[String]) -> Option<NaiveDateTime> {
+    let x = x.trim();
+    formats.iter().find_map(|fmt| {
+        DateTime::parse_from_str(x, fmt)
+            .map(|x| x.naive_utc())
+            .or_else(|_| NaiveDateTime::parse_from_str(x, fmt))
+            .or_else(|_| NaiveDate::parse_from_str(x, fmt).map(|x| x.and_time(Default::default())))
+            .ok()
+    })
+}
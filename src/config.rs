@@ -1,9 +1,17 @@
-use crate::cli::Cli;
+use crate::cli::{Cli, DuplicateKeys, Format, Malformed};
+use crate::comparator::Comparator;
+use crate::csv_reader::CsvDialect;
+use crate::fixed_width_reader::Layout;
+use crate::input::{self, InputFormat, InputOptions, Reader, STDIN};
+use crate::rules::Rules;
 use crate::sym::Sym;
+use crate::threshold::{Metric, Threshold};
+use crate::xlsx_reader::CellFormat;
 use crate::Interner;
 use anyhow::{bail, Context, Result};
-use csv::{ReaderBuilder, Trim};
 use itertools::Itertools;
+use log::{info, warn};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 fn check_dups(cols: &[String], msg: &str) -> Result<()> {
@@ -26,39 +34,146 @@ fn check_key_cols_presence(key_cols: &[String], cols: &[String], fname: &Path) -
     }
 }
 
-pub fn get_csv_headers<P: AsRef<Path>>(path: P, delimiter: u8) -> csv::Result<Vec<String>> {
-    Ok(ReaderBuilder::new()
-        .trim(Trim::All)
-        .delimiter(delimiter)
-        .from_path(path)?
-        .headers()?
-        .iter()
-        .map(|x| x.to_owned())
-        .collect())
-}
-
 pub struct Config {
     pub files: [PathBuf; 2],
-    pub delims: [u8; 2],
     pub key_cols: Vec<Sym>,
     pub common_cols: Vec<Sym>,
+    pub source_col: Option<Sym>, // read from both files, but not compared
     pub ignored_cols: [Vec<Sym>; 2],
+    pub column_order_differs: bool, // common columns appear in a different order in both files
+    pub duplicate_keys: DuplicateKeys,
+    pub malformed: Malformed,
+    pub null_tokens: Vec<Sym>,                 // values read as nulls
+    pub comparators: HashMap<Sym, Comparator>, // non-key columns not compared exactly
+    pub excluded_cols: Vec<Sym>,               // common columns ignored as per rules
+    pub display_names: HashMap<Sym, String>,
+    pub rules_file: Option<PathBuf>,
     pub out_file: PathBuf,
+    pub open_browser: bool,
+    pub format: Format,
+    pub thresholds: Vec<Threshold>, // conditions for failing, any difference fails if empty
 }
 
 impl Config {
-    pub fn try_from_cli(cli: Cli, interner: &mut Interner) -> Result<Self> {
-        let lh = get_csv_headers(&cli.left_file, cli.left_delim)
-            .with_context(|| format!("Failed to read {:?}", &cli.left_file.display()))?;
-        let rh = get_csv_headers(&cli.right_file, cli.right_delim)
-            .with_context(|| format!("Failed to read {:?}", &cli.right_file.display()))?;
+    /// Also returns the readers of both files, their headers having been read, so that inputs which
+    /// cannot be read twice (stdin or pipes) work.
+    pub fn try_from_cli(cli: Cli, interner: &mut Interner) -> Result<(Self, [Box<dyn Reader>; 2])> {
+        if cli.left_file == Path::new(STDIN) && cli.right_file == Path::new(STDIN) {
+            bail!("only one of the files can be read from stdin");
+        }
+        let load_layout = |path: &Option<PathBuf>| {
+            path.as_deref()
+                .map(|path| {
+                    Layout::from_file(path)
+                        .with_context(|| format!("Failed to read layout {:?}", path.display()))
+                })
+                .transpose()
+        };
+        let layouts = [
+            load_layout(&cli.left_layout)?,
+            load_layout(&cli.right_layout)?,
+        ];
+        // files with a layout are fixed-width unless told otherwise
+        let detect_format =
+            |format: Option<InputFormat>, layout: &Option<Layout>, path: &Path| match (
+                format, layout,
+            ) {
+                (Some(format), _) => Ok(format),
+                (None, Some(_)) => Ok(InputFormat::Fixed),
+                (None, None) => InputFormat::detect(path)
+                    .with_context(|| format!("Failed to read {:?}", path.display())),
+            };
+        let cell_format = CellFormat {
+            date_format: cli.sheet_date_format.clone(),
+            datetime_format: cli.sheet_datetime_format.clone(),
+            decimals: cli.sheet_decimals,
+        };
+        let [left_layout, right_layout] = layouts;
+        let paths = [
+            input::expand(&cli.left_file)
+                .with_context(|| format!("Failed to read {:?}", &cli.left_file.display()))?,
+            input::expand(&cli.right_file)
+                .with_context(|| format!("Failed to read {:?}", &cli.right_file.display()))?,
+        ];
+        let inputs = [
+            InputOptions {
+                format: detect_format(cli.left_format, &left_layout, &paths[0][0])?,
+                dialect: CsvDialect {
+                    delimiter: cli.left_delim.clone().into_bytes(),
+                    quote: cli.left_quote,
+                    escape: cli.left_escape,
+                    double_quote: !cli.left_no_double_quote,
+                    comment: cli.left_comment,
+                    skip_rows: cli.left_skip_rows,
+                    has_header: !cli.no_header,
+                    on_malformed: cli.on_malformed,
+                },
+                columns: cli.columns.clone(),
+                encoding: cli.left_encoding,
+                json_sample: cli.json_sample,
+                sheet: cli.left_sheet.clone(),
+                header_row: cli.left_header_row as usize,
+                cell_format: cell_format.clone(),
+                layout: left_layout,
+                source_column: cli.source_column.clone(),
+            },
+            InputOptions {
+                format: detect_format(cli.right_format, &right_layout, &paths[1][0])?,
+                dialect: CsvDialect {
+                    delimiter: cli.right_delim.clone().into_bytes(),
+                    quote: cli.right_quote,
+                    escape: cli.right_escape,
+                    double_quote: !cli.right_no_double_quote,
+                    comment: cli.right_comment,
+                    skip_rows: cli.right_skip_rows,
+                    has_header: !cli.no_header,
+                    on_malformed: cli.on_malformed,
+                },
+                columns: cli.columns.clone(),
+                encoding: cli.right_encoding,
+                json_sample: cli.json_sample,
+                sheet: cli.right_sheet.clone(),
+                header_row: cli.right_header_row as usize,
+                cell_format,
+                layout: right_layout,
+                source_column: cli.source_column.clone(),
+            },
+        ];
+        for (options, flag) in inputs.iter().zip(["--left-layout", "--right-layout"]) {
+            if options.format == InputFormat::Fixed && options.layout.is_none() {
+                bail!("{} is required for fixed-width files", flag);
+            }
+        }
+
+        let readers = [
+            input::open_reader(&paths[0], &inputs[0])
+                .with_context(|| format!("Failed to read {:?}", &cli.left_file.display()))?,
+            input::open_reader(&paths[1], &inputs[1])
+                .with_context(|| format!("Failed to read {:?}", &cli.right_file.display()))?,
+        ];
+        let lh = readers[0].headers().to_vec();
+        let rh = readers[1].headers().to_vec();
 
-        check_dups(&cli.key_cols, "duplicate keyCols:")?;
+        // without a header row, key columns may be given by position
+        let key_cols = match cli.no_header {
+            true => cli
+                .key_cols
+                .iter()
+                .map(|x| match x.parse::<usize>() {
+                    Ok(pos) if pos >= 1 && pos <= lh.len() => lh[pos - 1].clone(),
+                    Ok(pos) => format!("col{}", pos), // reported as missing below
+                    Err(_) => x.clone(),
+                })
+                .collect(),
+            false => cli.key_cols.clone(),
+        };
+
+        check_dups(&key_cols, "duplicate keyCols:")?;
         check_dups(&lh, "duplicate columns in left file:")?;
         check_dups(&rh, "duplicate columns in right file:")?;
 
-        check_key_cols_presence(&cli.key_cols, &lh, &cli.left_file)?;
-        check_key_cols_presence(&cli.key_cols, &rh, &cli.right_file)?;
+        check_key_cols_presence(&key_cols, &lh, &cli.left_file)?;
+        check_key_cols_presence(&key_cols, &rh, &cli.right_file)?;
 
         let lh = lh
             .into_iter()
@@ -69,27 +184,128 @@ impl Config {
             .map(|x| interner.get_or_intern(x))
             .collect::<Vec<_>>();
 
-        let common_cols = lh.iter().filter(|&x| rh.contains(x)).copied().collect_vec();
-        if common_cols.len() == cli.key_cols.len() {
+        let key_cols = key_cols
+            .into_iter()
+            .map(|x| interner.get_or_intern(x))
+            .collect::<Vec<_>>();
+
+        let rules = match &cli.rules {
+            Some(path) => Rules::from_file(path)
+                .with_context(|| format!("Failed to read rules from {:?}", path.display()))?,
+            None => Rules::default(),
+        };
+
+        let mut comparators = HashMap::new();
+        let mut excluded_cols = Vec::new();
+        let mut display_names = HashMap::new();
+        for (col, rule) in rules.columns {
+            let sym = interner.get_or_intern(&col);
+            if !lh.contains(&sym) && !rh.contains(&sym) {
+                warn!("rules given for column missing from both files: {}", col);
+            }
+            let comparator = rule.comparator(&col)?;
+            if key_cols.contains(&sym) && (rule.ignore || comparator.is_some()) {
+                bail!(
+                    "key column {} can only be given a display name in rules",
+                    col
+                );
+            }
+            if rule.ignore {
+                excluded_cols.push(sym);
+            }
+            if let Some(comparator) = comparator {
+                comparators.insert(sym, comparator);
+            }
+            if let Some(display_name) = rule.display_name {
+                display_names.insert(sym, display_name);
+            }
+        }
+
+        // the file rows were read from is shown next to their key in the report, but never compared
+        let source_col = cli
+            .source_column
+            .as_ref()
+            .map(|x| interner.get_or_intern(x));
+        if let Some(col) = source_col.filter(|x| key_cols.contains(x)) {
+            bail!(
+                "source column {} cannot be a key column",
+                interner.resolve(col).unwrap()
+            );
+        }
+
+        let in_both = |x: &Sym, other: &[Sym]| {
+            other.contains(x) && !excluded_cols.contains(x) && Some(*x) != source_col
+        };
+        let common_cols = lh
+            .iter()
+            .filter(|&x| in_both(x, &rh))
+            .copied()
+            .collect_vec();
+        if common_cols.len() == key_cols.len() {
             bail!("no non-key columns present")
         }
 
+        let column_order_differs = !rh
+            .iter()
+            .filter(|&x| in_both(x, &lh))
+            .eq(common_cols.iter());
+        if column_order_differs {
+            info!("common columns are in a different order in both files, comparing them by name");
+        }
+
         let ignored_cols = [
             lh.iter().filter(|&x| !rh.contains(x)).copied().collect(),
             rh.iter().filter(|&x| !lh.contains(x)).copied().collect(),
         ];
 
-        Ok(Config {
+        // tolerances given on the command line take precedence over rules
+        for (col, tolerance) in cli.tolerance {
+            let sym = interner.get_or_intern(&col);
+            if key_cols.contains(&sym) || !common_cols.contains(&sym) {
+                bail!("tolerance given for unknown non-key column: {}", col);
+            }
+            comparators.insert(sym, Comparator::Numeric(tolerance));
+        }
+
+        // values are trimmed when read, and so are the tokens
+        let null_tokens = cli
+            .null_tokens
+            .iter()
+            .map(|x| interner.get_or_intern(x.trim()))
+            .collect();
+
+        let mut thresholds = cli.fail_if;
+        if let Some(pct) = cli.max_diff_cell_pct {
+            thresholds.push(Threshold::new(Metric::DiffCellPct, ">", pct));
+        }
+
+        let config = Config {
             files: [cli.left_file, cli.right_file],
-            delims: [cli.left_delim, cli.right_delim],
-            key_cols: cli
-                .key_cols
-                .into_iter()
-                .map(|x| interner.get_or_intern(x))
-                .collect(),
+            key_cols,
             common_cols,
+            source_col,
             ignored_cols,
+            column_order_differs,
+            duplicate_keys: cli.on_duplicate_keys,
+            malformed: cli.on_malformed,
+            null_tokens,
+            comparators,
+            excluded_cols,
+            display_names,
+            rules_file: cli.rules,
             out_file: cli.out_file,
-        })
+            open_browser: !cli.no_open,
+            format: cli.format,
+            thresholds,
+        };
+        Ok((config, readers))
+    }
+
+    /// Name of a column as shown in the report.
+    pub fn col_name<'a>(&'a self, col: Sym, interner: &'a Interner) -> &'a str {
+        match self.display_names.get(&col) {
+            Some(name) => name,
+            None => interner.resolve(col).unwrap(),
+        }
     }
 }
@@ -0,0 +1,400 @@
+use crate::cli::Malformed;
+use crate::input::{self, MalformedRecord, Reader};
+use crate::sym::{Interner, Sym, NULL};
+use anyhow::bail;
+use bitvec::vec::BitVec;
+use csv::{ByteRecord, ReaderBuilder, Terminator, WriterBuilder};
+use encoding_rs::Encoding;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str;
+
+// stands in for multi-character delimiters, which the csv crate does not support
+const UNIT_SEPARATOR: u8 = 0x1f;
+
+/// How a delimited text file is to be parsed.
+#[derive(Clone)]
+pub struct CsvDialect {
+    pub delimiter: Vec<u8>, // one or more bytes
+    pub quote: u8,
+    pub escape: Option<u8>,  // escapes quotes within quoted fields, if given
+    pub double_quote: bool,  // whether doubled quotes within quoted fields are quotes
+    pub comment: Option<u8>, // lines starting with this are skipped
+    pub skip_rows: usize,    // lines to skip before the header row
+    pub has_header: bool,
+    pub on_malformed: Malformed, // for records with more or fewer fields than the header
+}
+
+// replaces a multi-character delimiter with `UNIT_SEPARATOR`, except within quoted fields and
+// comment lines, following the csv crate in that quotes only start a quoted field at its start
+struct DelimiterRewriter<R> {
+    rdr: R,
+    delimiter: Vec<u8>,
+    quote: u8,
+    escape: Option<u8>,
+    double_quote: bool,
+    comment: Option<u8>,
+    line_start: bool,  // outside quotes, at the start of a line
+    field_start: bool, // outside quotes, at the start of a field
+    in_quotes: bool,
+    escaped: bool,
+    closed_quote: bool, // right after the closing quote of a field, which may be doubled
+    in_comment: bool,
+    buf: Vec<u8>, // read but not yet rewritten, at most a partial delimiter
+    out: Vec<u8>,
+    out_pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DelimiterRewriter<R> {
+    fn new(rdr: R, dialect: &CsvDialect) -> Self {
+        Self {
+            rdr,
+            delimiter: dialect.delimiter.clone(),
+            quote: dialect.quote,
+            escape: dialect.escape,
+            double_quote: dialect.double_quote,
+            comment: dialect.comment,
+            line_start: true,
+            field_start: true,
+            in_quotes: false,
+            escaped: false,
+            closed_quote: false,
+            in_comment: false,
+            buf: Vec::new(),
+            out: Vec::new(),
+            out_pos: 0,
+            eof: false,
+        }
+    }
+
+    fn rewrite(&mut self) {
+        self.out.clear();
+        self.out_pos = 0;
+        let mut idx = 0;
+        while idx < self.buf.len() {
+            let rest = &self.buf[idx..];
+            let x = rest[0];
+            if self.in_comment {
+                if x == b'\n' {
+                    self.in_comment = false;
+                    self.line_start = true;
+                    self.field_start = true;
+                }
+            } else if self.in_quotes {
+                if self.escaped {
+                    self.escaped = false;
+                } else if Some(x) == self.escape {
+                    self.escaped = true;
+                } else if x == self.quote {
+                    self.in_quotes = false;
+                    self.closed_quote = true;
+                }
+            } else if self.closed_quote && self.double_quote && x == self.quote {
+                // a doubled quote within a quoted field
+                self.closed_quote = false;
+                self.in_quotes = true;
+            } else if self.line_start && Some(x) == self.comment {
+                self.in_comment = true;
+                self.line_start = false;
+                self.field_start = false;
+            } else if self.field_start && x == self.quote {
+                self.in_quotes = true;
+                self.line_start = false;
+                self.field_start = false;
+            } else if rest.starts_with(&self.delimiter) {
+                self.out.push(UNIT_SEPARATOR);
+                idx += self.delimiter.len();
+                self.line_start = false;
+                self.field_start = true;
+                self.closed_quote = false;
+                continue;
+            } else if !self.eof && self.delimiter.starts_with(rest) {
+                break; // possibly a delimiter continuing in the next read
+            } else {
+                // quotes within an unquoted field are taken as is
+                self.closed_quote = false;
+                self.line_start = matches!(x, b'\n' | b'\r');
+                self.field_start = self.line_start;
+            }
+            self.out.push(x);
+            idx += 1;
+        }
+        self.buf.drain(..idx);
+    }
+}
+
+impl<R: Read> Read for DelimiterRewriter<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.out_pos == self.out.len() {
+            if self.eof && self.buf.is_empty() {
+                return Ok(0);
+            }
+            let len = self.buf.len();
+            self.buf.resize(len + 8 * 1024, 0);
+            let n = self.rdr.read(&mut self.buf[len..])?;
+            self.buf.truncate(len + n);
+            self.eof = n == 0;
+            self.rewrite();
+        }
+        let n = out.len().min(self.out.len() - self.out_pos);
+        out[..n].copy_from_slice(&self.out[self.out_pos..self.out_pos + n]);
+        self.out_pos += n;
+        Ok(n)
+    }
+}
+
+// counts the lines read, so that records can be given their line numbers in the file even though
+// the csv crate does not count the comment lines it skips
+struct LineCounter<R> {
+    rdr: R,
+    pos: u64,                // bytes read so far
+    newlines: VecDeque<u64>, // positions of the newlines read ahead of the last record
+    lines: u64,              // lines ending before the last record
+}
+
+impl<R: Read> LineCounter<R> {
+    fn new(rdr: R) -> Self {
+        Self {
+            rdr,
+            pos: 0,
+            newlines: VecDeque::new(),
+            lines: 0,
+        }
+    }
+
+    // line number of the byte at `pos`, which is not to be before that of an earlier call
+    fn line_at(&mut self, pos: u64) -> u64 {
+        while self.newlines.front().is_some_and(|&x| x < pos) {
+            self.newlines.pop_front();
+            self.lines += 1;
+        }
+        self.lines + 1
+    }
+}
+
+impl<R: Read> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.rdr.read(buf)?;
+        let pos = self.pos;
+        self.newlines.extend(
+            buf[..n]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &x)| x == b'\n')
+                .map(|(idx, _)| pos + idx as u64),
+        );
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+pub struct CsvReader {
+    rdr: csv::Reader<LineCounter<Box<dyn Read>>>,
+    path: PathBuf,
+    dialect: CsvDialect,
+    headers: Vec<String>,
+    record: ByteRecord,
+    malformed: Vec<MalformedRecord>,
+    last_line: u64, // last line of the current record, not counting skipped rows
+}
+
+impl CsvReader {
+    /// Without a header row, the columns are named `columns` if given, or `col1`, `col2`, ...
+    pub fn new(
+        path: &Path,
+        dialect: &CsvDialect,
+        columns: Option<&[String]>,
+        encoding: Option<&'static Encoding>,
+    ) -> anyhow::Result<Self> {
+        let mut input = BufReader::new(input::open_text(path, encoding)?);
+        let mut line = Vec::new();
+        for _ in 0..dialect.skip_rows {
+            line.clear();
+            input.read_until(b'\n', &mut line)?;
+        }
+        let (input, delimiter): (Box<dyn Read>, _) = match dialect.delimiter[..] {
+            [delimiter] => (Box::new(input), delimiter),
+            _ => (
+                Box::new(DelimiterRewriter::new(input, dialect)),
+                UNIT_SEPARATOR,
+            ),
+        };
+
+        let has_header = dialect.has_header;
+        let mut rdr = ReaderBuilder::new()
+            //.trim(Trim::All)    // much slower
+            .delimiter(delimiter)
+            .quote(dialect.quote)
+            .escape(dialect.escape)
+            .double_quote(dialect.double_quote)
+            .comment(dialect.comment)
+            .has_headers(has_header)
+            .flexible(true) // field counts are checked against the headers when reading
+            .from_reader(LineCounter::new(input));
+
+        // without a header row, this is the first record which is still to be read
+        let first = rdr.headers()?;
+        let headers = match (has_header, columns) {
+            (true, _) => first.iter().map(|x| x.trim().to_owned()).collect(),
+            (false, Some(columns)) => {
+                if !first.is_empty() && first.len() != columns.len() {
+                    bail!(
+                        "{} columns given but the first record has {} fields",
+                        columns.len(),
+                        first.len()
+                    );
+                }
+                columns.iter().map(|x| x.trim().to_owned()).collect()
+            }
+            (false, None) => (1..=first.len()).map(|x| format!("col{}", x)).collect(),
+        };
+
+        Ok(Self {
+            rdr,
+            path: path.to_owned(),
+            dialect: dialect.clone(),
+            headers,
+            record: ByteRecord::new(),
+            malformed: Vec::new(),
+            last_line: 0,
+        })
+    }
+
+    // first line of the current record in the file
+    fn line(&self) -> u64 {
+        // quoted fields may span lines
+        let newlines = self
+            .record
+            .as_slice()
+            .iter()
+            .filter(|&&x| x == b'\n')
+            .count();
+        // lines skipped before the header row are not seen by the csv reader
+        self.last_line - newlines as u64 + self.dialect.skip_rows as u64
+    }
+
+    // renders the current record as it would appear in the file, quoting fields where needed
+    fn raw_record(&self) -> String {
+        let delimiter = match self.dialect.delimiter[..] {
+            [delimiter] => delimiter,
+            _ => UNIT_SEPARATOR,
+        };
+        let mut wtr = WriterBuilder::new()
+            .delimiter(delimiter)
+            .quote(self.dialect.quote)
+            .terminator(Terminator::Any(b'\n'))
+            .from_writer(Vec::new());
+        // writing to memory does not fail
+        wtr.write_byte_record(&self.record).unwrap();
+        let mut raw = wtr.into_inner().unwrap();
+        if raw.last() == Some(&b'\n') {
+            raw.pop();
+        }
+        let raw = String::from_utf8_lossy(&raw);
+        match self.dialect.delimiter[..] {
+            [_] => raw.into_owned(),
+            _ => raw.replace(
+                UNIT_SEPARATOR as char,
+                &String::from_utf8_lossy(&self.dialect.delimiter),
+            ),
+        }
+    }
+}
+
+impl Reader for CsvReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        loop {
+            if !self.rdr.read_byte_record(&mut self.record)? {
+                return Ok(false);
+            }
+            // the position of a record is where reading it started, before any comment lines, so
+            // its lines are counted up to where it ends
+            let end = self.rdr.position().byte();
+            self.last_line = self.rdr.get_mut().line_at(end.saturating_sub(1));
+            let (expected, found) = (self.headers.len(), self.record.len());
+            if expected == found {
+                break;
+            }
+            let line = self.line();
+            let reason = format!("expected {} fields, found {}", expected, found);
+            if self.dialect.on_malformed == Malformed::Error {
+                bail!("line {}: {} (see --on-malformed)", line, reason);
+            }
+            self.malformed.push(MalformedRecord {
+                file: self.path.clone(),
+                line,
+                reason,
+                raw: self.raw_record(),
+            });
+            if self.dialect.on_malformed == Malformed::Pad {
+                break;
+            }
+        }
+
+        record.clear();
+        for (idx, _) in mask.iter().enumerate().filter(|(_, selected)| **selected) {
+            record.push(match self.record.get(idx) {
+                Some(x) => match str::from_utf8(x) {
+                    Ok(x) => interner.get_or_intern(x.trim()),
+                    Err(_) => bail!("line {}: field {} is not valid UTF-8", self.line(), idx + 1),
+                },
+                None => interner.get_or_intern_static(NULL), // padded
+            });
+        }
+        Ok(true)
+    }
+
+    fn take_malformed(&mut self) -> Vec<MalformedRecord> {
+        mem::take(&mut self.malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(input: &str, comment: Option<u8>) -> String {
+        let dialect = CsvDialect {
+            delimiter: b"||".to_vec(),
+            quote: b'"',
+            escape: None,
+            double_quote: true,
+            comment,
+            skip_rows: 0,
+            has_header: true,
+            on_malformed: Malformed::Error,
+        };
+        let mut out = String::new();
+        DelimiterRewriter::new(input.as_bytes(), &dialect)
+            .read_to_string(&mut out)
+            .unwrap();
+        out.replace(UNIT_SEPARATOR as char, ",")
+    }
+
+    #[test]
+    fn delimiter_rewriter() {
+        // quotes only start a quoted field at its start
+        assert_eq!(
+            rewrite("id||a\n1||5\" screen\n2||q\n", None),
+            "id,a\n1,5\" screen\n2,q\n"
+        );
+        assert_eq!(rewrite("1||\"a||\"\"b\"||c\n", None), "1,\"a||\"\"b\",c\n");
+        // comment lines are passed through as is
+        assert_eq!(
+            rewrite("id||a\n# it\"s||x\n2||q\n", Some(b'#')),
+            "id,a\n# it\"s||x\n2,q\n"
+        );
+    }
+}
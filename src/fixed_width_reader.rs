@@ -0,0 +1,143 @@
+use crate::input::{self, Reader};
+use crate::sym::{Interner, Sym};
+use anyhow::{bail, Context, Result};
+use bitvec::vec::BitVec;
+use encoding_rs::Encoding;
+use serde::Deserialize;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Layout of a fixed-width file, read from a TOML file such as:
+///
+/// ```toml
+/// skip = 1 # header record
+///
+/// [[columns]]
+/// name = "id"
+/// start = 1
+/// length = 6
+///
+/// [[columns]]
+/// name = "name"
+/// start = 7
+/// length = 20
+/// trim = false
+/// ```
+///
+/// Positions are 1-based and count characters.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    #[serde(default)]
+    pub skip: usize, // lines at the start of the file to skip
+    pub columns: Vec<ColumnLayout>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnLayout {
+    pub name: String,
+    pub start: usize,
+    pub length: usize,
+    #[serde(default = "default_trim")]
+    pub trim: bool, // whether to trim surrounding whitespace
+}
+
+fn default_trim() -> bool {
+    true
+}
+
+impl Layout {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let layout: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        if layout.columns.is_empty() {
+            bail!("no columns in layout");
+        }
+        for col in layout.columns.iter() {
+            if col.start == 0 || col.length == 0 {
+                bail!("column {}: start and length should be at least 1", col.name);
+            }
+        }
+        Ok(layout)
+    }
+}
+
+/// Reads a fixed-width text file, optionally compressed, splitting each line into columns as per
+/// its layout. Blank lines are skipped, and fields past the end of a short line are read as empty.
+pub struct FixedWidthReader {
+    rdr: BufReader<Box<dyn Read>>,
+    columns: Vec<ColumnLayout>,
+    headers: Vec<String>,
+    line: String,
+    line_no: usize,
+    offsets: Vec<usize>, // byte offset of each character of the line, if not ASCII
+}
+
+impl FixedWidthReader {
+    pub fn new(path: &Path, layout: &Layout, encoding: Option<&'static Encoding>) -> Result<Self> {
+        let mut rdr = BufReader::new(input::open_text(path, encoding)?);
+        let mut line = String::new();
+        for line_no in 1..=layout.skip {
+            line.clear();
+            rdr.read_line(&mut line)
+                .with_context(|| format!("line {}", line_no))?;
+        }
+        Ok(Self {
+            rdr,
+            columns: layout.columns.clone(),
+            headers: layout.columns.iter().map(|x| x.name.clone()).collect(),
+            line,
+            line_no: layout.skip,
+            offsets: Vec::new(),
+        })
+    }
+}
+
+impl Reader for FixedWidthReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> Result<bool> {
+        let line = loop {
+            self.line.clear();
+            self.line_no += 1;
+            let n = self
+                .rdr
+                .read_line(&mut self.line)
+                .with_context(|| format!("line {}", self.line_no))?;
+            if n == 0 {
+                return Ok(false);
+            }
+            let line = self.line.trim_end_matches(['\n', '\r']);
+            if !line.is_empty() {
+                break line;
+            }
+        };
+
+        // character positions are byte positions in ASCII lines
+        let ascii = line.is_ascii();
+        if !ascii {
+            self.offsets.clear();
+            self.offsets.extend(line.char_indices().map(|(idx, _)| idx));
+        }
+        let offset = |pos: usize| match ascii {
+            true => pos.min(line.len()),
+            false => self.offsets.get(pos).copied().unwrap_or(line.len()),
+        };
+
+        record.clear();
+        for col in mask.iter_ones().map(|idx| &self.columns[idx]) {
+            let start = offset(col.start - 1);
+            let field = &line[start..offset(col.start - 1 + col.length).max(start)];
+            record.push(interner.get_or_intern(if col.trim { field.trim() } else { field }));
+        }
+        Ok(true)
+    }
+}
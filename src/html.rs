@@ -1,12 +1,50 @@
-use crate::sym::Sym;
-use crate::table::Comparison;
+use crate::cli::{DuplicateKeys, Malformed};
+use crate::comparator::{numeric_delta, Comparator};
+use crate::sym::{Sym, NULL};
 use crate::table::SlicesFromMmaps;
+use crate::table::{get_aligned_non_key_cols_mmaps, resolve, Comparison};
 use crate::{Config, Interner, KeyedTable};
 use itertools::Itertools;
 use log::info;
+use std::fmt;
 use std::io::{self, BufWriter, Write};
 use string_interner::symbol::Symbol;
 
+/// Displays a string with the characters that are special in HTML escaped, so that it can be
+/// written as element content or as a quoted attribute value.
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rest = self.0;
+        while let Some(idx) = rest.find(['&', '<', '>', '"', '\'']) {
+            f.write_str(&rest[..idx])?;
+            f.write_str(match rest.as_bytes()[idx] {
+                b'&' => "&amp;",
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'"' => "&quot;",
+                _ => "&#39;",
+            })?;
+            rest = &rest[idx + 1..];
+        }
+        f.write_str(rest)
+    }
+}
+
+/// Displays a cell value escaped, or as an italic `null` if it is missing.
+struct Value<'a>(&'a str);
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == NULL {
+            f.write_str("<i>null</i>")
+        } else {
+            Escaped(self.0).fmt(f)
+        }
+    }
+}
+
 fn append_config_table<W: Write>(
     out: &mut W,
     config: &Config,
@@ -20,30 +58,77 @@ fn append_config_table<W: Write>(
          <tr><th scope='row'>Right file</th><td>{}</td></tr>\
          <tr><th scope='row'>Key columns</th><td>{}</td></tr>\
          <tr><th scope='row'>Common non-key columns</th><td>{}</td></tr>",
-        // todo: html escape
-        config.files[0].display(),
-        config.files[1].display(),
-        // todo: html escape and style like bootstrap badges instead of comma separator
+        Escaped(&config.files[0].display().to_string()),
+        Escaped(&config.files[1].display().to_string()),
+        // todo: style like bootstrap badges instead of comma separator
         config
             .key_cols
             .iter()
-            .map(|&x| interner.resolve(x).unwrap())
+            .map(|&x| Escaped(config.col_name(x, interner)))
             .join(", "),
         config
             .common_cols
             .iter()
             .filter(|x| !config.key_cols.contains(x))
-            .map(|&x| interner.resolve(x).unwrap())
+            .map(|&x| Escaped(config.col_name(x, interner)))
             .join(", "),
     )?;
 
+    if let Some(rules_file) = &config.rules_file {
+        write!(
+            out,
+            "<tr><th scope='row'>Rules file</th><td>{}</td></tr>",
+            Escaped(&rules_file.display().to_string())
+        )?;
+    }
+
+    if let Some(source_col) = config.source_col {
+        write!(
+            out,
+            "<tr><th scope='row'>Source column (not compared)</th><td>{}</td></tr>",
+            Escaped(config.col_name(source_col, interner))
+        )?;
+    }
+
+    if !config.null_tokens.is_empty() {
+        write!(
+            out,
+            "<tr><th scope='row'>Values read as nulls</th><td>{}</td></tr>",
+            config
+                .null_tokens
+                .iter()
+                .map(|&x| format!("&quot;{}&quot;", Escaped(interner.resolve(x).unwrap())))
+                .join(", "),
+        )?;
+    }
+
+    if config.column_order_differs {
+        write!(
+            out,
+            "<tr><th scope='row'>Column order</th>\
+             <td>differs between files (columns are compared by name)</td></tr>",
+        )?;
+    }
+
+    if !config.excluded_cols.is_empty() {
+        write!(
+            out,
+            "<tr><th scope='row'>Columns ignored by rules</th><td>{}</td></tr>",
+            config
+                .excluded_cols
+                .iter()
+                .map(|&x| Escaped(config.col_name(x, interner)))
+                .join(", "),
+        )?;
+    }
+
     if !config.ignored_cols[0].is_empty() {
         write!(
             out,
             "<tr><th scope='row'>Columns only in left file (ignored)</th><td>{}</td></tr>",
             config.ignored_cols[0]
                 .iter()
-                .map(|&x| interner.resolve(x).unwrap())
+                .map(|&x| Escaped(config.col_name(x, interner)))
                 .join(", "),
         )?;
     }
@@ -54,7 +139,7 @@ fn append_config_table<W: Write>(
             "<tr><th scope='row'>Columns only in right file (ignored)</th><td>{}</td></tr>",
             config.ignored_cols[1]
                 .iter()
-                .map(|&x| interner.resolve(x).unwrap())
+                .map(|&x| Escaped(config.col_name(x, interner)))
                 .join(", ")
         )?;
     }
@@ -68,6 +153,7 @@ fn append_stats_table<W: Write>(
     comparison: &Comparison,
     lt_count: (usize, usize), // left table row, column count
     rt_count: (usize, usize), // right table row, column count
+    malformed_count: usize,   // malformed records of both files
 ) -> io::Result<()> {
     write!(
         out,
@@ -77,12 +163,18 @@ fn append_stats_table<W: Write>(
         <th scope='col'>Matched</th>\
         <th scope='col'><a href='#d'>Diffs</a></th>\
         <th scope='col'><a href='#l'>Only in left</a></th>\
-        <th scope='col'><a href='#r'>Only in right</a></th>\
-      </tr>\
-    </thead>\
-    <tbody>\
-      <tr>",
+        <th scope='col'><a href='#r'>Only in right</a></th>",
     )?;
+    if !comparison.duplicate_keys.is_empty() {
+        write!(out, "<th scope='col'><a href='#k'>Duplicate keys</a></th>")?;
+    }
+    if malformed_count > 0 {
+        write!(
+            out,
+            "<th scope='col'><a href='#m'>Malformed records</a></th>"
+        )?;
+    }
+    write!(out, "</tr></thead><tbody><tr>")?;
 
     let total_cell_count = (lt_count.0 * lt_count.1) + (rt_count.0 * rt_count.1);
 
@@ -104,12 +196,21 @@ fn append_stats_table<W: Write>(
     write!(
         out,
         "<td>{} rows ({:.2}% cells)</td>\
-         <td>{} rows ({:.2}% cells)</td>",
+         <td>{} rows ({:.2}% cells",
         comparison.match_row_count,
         round((2 * 100 * comparison.match_cell_count) as f64 / total_cell_count as f64),
         comparison.diff_row_count,
         round((2 * 100 * comparison.diff_cell_count) as f64 / total_cell_count as f64),
     )?;
+    // null vs value diffs are often an export artifact rather than a change of value
+    if comparison.null_diff_cell_count > 0 {
+        write!(
+            out,
+            ", of which {} cells null vs value",
+            comparison.null_diff_cell_count
+        )?;
+    }
+    write!(out, ")</td>")?;
 
     let mut write_only_stats =
         |count: (usize, usize), only_row_count, only_col_count| -> io::Result<()> {
@@ -140,11 +241,19 @@ fn append_stats_table<W: Write>(
         rt_only_col_count,
     )?;
 
+    if !comparison.duplicate_keys.is_empty() {
+        write!(out, "<td>{} keys</td>", comparison.duplicate_keys.len())?;
+    }
+    if malformed_count > 0 {
+        write!(out, "<td>{} records</td>", malformed_count)?;
+    }
+
     write!(out, "</tr></tbody></table>")
 }
 
 fn write_headers<W: Write>(
     out: &mut W,
+    config: &Config,
     cols: &[Sym],
     colspan: u8,
     interner: &Interner,
@@ -152,31 +261,37 @@ fn write_headers<W: Write>(
     for &c in cols.iter() {
         write!(out, "<th scope='col'")?;
         if colspan > 1 {
-            write!(out, "colspan='{}'", colspan)?;
+            write!(out, " colspan='{}'", colspan)?;
         }
-        write!(out, ">{}</th>", interner.resolve(c).unwrap())?;
+        write!(out, ">{}</th>", Escaped(config.col_name(c, interner)))?;
     }
     Ok(())
 }
 
 fn append_diff_table<W: Write>(
     out: &mut W,
+    config: &Config,
     (lt, rt): (&KeyedTable, &KeyedTable),
     interner: &Interner,
     comparison: &Comparison,
 ) -> anyhow::Result<()> {
     write!(out, "<table><thead><tr>")?;
 
-    write_headers(out, &lt.key_columns, 1, interner)?;
-    write_headers(out, &lt.non_key_columns, 2, interner)?;
+    write_headers(out, config, &lt.key_columns, 1, interner)?;
+    write_headers(out, config, lt.source_column.as_slice(), 2, interner)?;
+    write_headers(out, config, &lt.non_key_columns, 2, interner)?;
 
     write!(out, "</tr></thead><tbody>")?;
 
     let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
     let lt_key_cols_slices = lt_key_cols_mmaps.to_slices()?;
 
-    let lt_non_key_cols_mmaps = lt.get_cols_mmaps(false)?;
-    let rt_non_key_cols_mmaps = rt.get_cols_mmaps(false)?;
+    let lt_source_col_mmaps = lt.get_source_col_mmaps()?;
+    let lt_source_col_slices = lt_source_col_mmaps.to_slices()?;
+    let rt_source_col_mmaps = rt.get_source_col_mmaps()?;
+    let rt_source_col_slices = rt_source_col_mmaps.to_slices()?;
+
+    let [lt_non_key_cols_mmaps, rt_non_key_cols_mmaps] = get_aligned_non_key_cols_mmaps(lt, rt)?;
     let lt_non_key_cols_slices = lt_non_key_cols_mmaps.to_slices()?;
     let rt_non_key_cols_slices = rt_non_key_cols_mmaps.to_slices()?;
 
@@ -194,15 +309,33 @@ fn append_diff_table<W: Write>(
             write!(
                 out,
                 "<th scope='row'>{}</th>",
-                interner.resolve(sym).unwrap()
+                Value(interner.resolve(sym).unwrap())
+            )?;
+        }
+
+        // write the files both rows were read from
+        for (&lt_source_col_slice, &rt_source_col_slice) in
+            lt_source_col_slices.iter().zip(rt_source_col_slices.iter())
+        {
+            write!(
+                out,
+                "<td>{}</td><td>{}</td>",
+                Value(resolve(interner, unsafe {
+                    *lt_source_col_slice.get_unchecked(lt_idx)
+                })),
+                Value(resolve(interner, unsafe {
+                    *rt_source_col_slice.get_unchecked(rt_idx)
+                }))
             )?;
         }
 
         // write non-key column values
-        for ((&lt_non_key_col_slice, &rt_non_key_col_slice), col_match) in lt_non_key_cols_slices
-            .iter()
-            .zip(rt_non_key_cols_slices.iter())
-            .zip(comparison.tt.iter())
+        for (((&lt_non_key_col_slice, &rt_non_key_col_slice), col_match), col) in
+            lt_non_key_cols_slices
+                .iter()
+                .zip(rt_non_key_cols_slices.iter())
+                .zip(comparison.tt.iter())
+                .zip(lt.non_key_columns.iter())
         {
             let lt_sym =
                 Sym::try_from_usize(unsafe { *lt_non_key_col_slice.get_unchecked(lt_idx) })
@@ -213,18 +346,29 @@ fn append_diff_table<W: Write>(
                 write!(
                     out,
                     "<td colspan='2' class='p'>{}</td>",
-                    interner.resolve(lt_sym).unwrap()
+                    Value(interner.resolve(lt_sym).unwrap())
                 )?;
             } else {
                 let rt_sym =
                     Sym::try_from_usize(unsafe { *rt_non_key_col_slice.get_unchecked(rt_idx) })
                         .unwrap();
+                let (lt_val, rt_val) = (
+                    interner.resolve(lt_sym).unwrap(),
+                    interner.resolve(rt_sym).unwrap(),
+                );
                 write!(
                     out,
-                    "<td class='f'>{}</td><td class='f'>{}</td>",
-                    interner.resolve(lt_sym).unwrap(),
-                    interner.resolve(rt_sym).unwrap()
+                    "<td class='f'>{}</td><td class='f'>{}",
+                    Value(lt_val),
+                    Value(rt_val)
                 )?;
+                // show by how much numeric values are off, since they exceed the tolerance
+                if let Some(Comparator::Numeric(_)) = config.comparators.get(col) {
+                    if let Some(delta) = numeric_delta(lt_val, rt_val) {
+                        write!(out, " <small>(&Delta; {})</small>", delta)?;
+                    }
+                }
+                write!(out, "</td>")?;
             }
         }
         write!(out, "</tr>")?;
@@ -235,20 +379,25 @@ fn append_diff_table<W: Write>(
 
 fn append_only_table<W: Write>(
     out: &mut W,
+    config: &Config,
     t: &KeyedTable,
     interner: &Interner,
     t_only_indices: &[usize],
 ) -> anyhow::Result<()> {
     write!(out, "<table><thead><tr>")?;
 
-    write_headers(out, &t.key_columns, 1, interner)?;
-    write_headers(out, &t.non_key_columns, 1, interner)?;
+    write_headers(out, config, &t.key_columns, 1, interner)?;
+    write_headers(out, config, t.source_column.as_slice(), 1, interner)?;
+    write_headers(out, config, &t.non_key_columns, 1, interner)?;
 
     write!(out, "</tr></thead><tbody>")?;
 
     let key_cols_mmaps = t.get_cols_mmaps(true)?;
     let key_cols_slices = key_cols_mmaps.to_slices()?;
 
+    let source_col_mmaps = t.get_source_col_mmaps()?;
+    let source_col_slices = source_col_mmaps.to_slices()?;
+
     let non_key_cols_mmaps = t.get_cols_mmaps(false)?;
     let non_key_cols_slices = non_key_cols_mmaps.to_slices()?;
 
@@ -261,15 +410,15 @@ fn append_only_table<W: Write>(
             write!(
                 out,
                 "<th scope='row'>{}</th>",
-                interner.resolve(sym).unwrap()
+                Value(interner.resolve(sym).unwrap())
             )?;
         }
 
-        // write non-key column values
-        for &non_key_col_slice in non_key_cols_slices.iter() {
+        // write non-key column values, after the file the row was read from
+        for &non_key_col_slice in source_col_slices.iter().chain(non_key_cols_slices.iter()) {
             let sym =
                 Sym::try_from_usize(unsafe { *non_key_col_slice.get_unchecked(idx) }).unwrap();
-            write!(out, "<td>{}</td>", interner.resolve(sym).unwrap())?;
+            write!(out, "<td>{}</td>", Value(interner.resolve(sym).unwrap()))?;
         }
 
         write!(out, "</tr>")?;
@@ -278,6 +427,88 @@ fn append_only_table<W: Write>(
     Ok(())
 }
 
+fn append_duplicate_keys_table<W: Write>(
+    out: &mut W,
+    config: &Config,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+) -> anyhow::Result<()> {
+    write!(out, "<table><thead><tr>")?;
+
+    write_headers(out, config, &lt.key_columns, 1, interner)?;
+    write!(
+        out,
+        "<th scope='col'>Rows in left</th><th scope='col'>Rows in right</th>"
+    )?;
+
+    write!(out, "</tr></thead><tbody>")?;
+
+    let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
+    let rt_key_cols_mmaps = rt.get_cols_mmaps(true)?;
+    let key_cols_slices = [
+        lt_key_cols_mmaps.to_slices()?,
+        rt_key_cols_mmaps.to_slices()?,
+    ];
+
+    for dup in comparison.duplicate_keys.iter() {
+        write!(out, "<tr>")?;
+
+        // write key column values from whichever table has the duplicates
+        let (side, idx) = match dup.indices {
+            [Some(idx), _] => (0, idx),
+            [None, Some(idx)] => (1, idx),
+            [None, None] => unreachable!("duplicate key should be present in some table"),
+        };
+        for &key_col_slice in key_cols_slices[side].iter() {
+            let sym = Sym::try_from_usize(unsafe { *key_col_slice.get_unchecked(idx) }).unwrap();
+            write!(
+                out,
+                "<th scope='row'>{}</th>",
+                Value(interner.resolve(sym).unwrap())
+            )?;
+        }
+
+        write!(
+            out,
+            "<td>{}</td><td>{}</td></tr>",
+            dup.counts[0], dup.counts[1]
+        )?;
+    }
+    write!(out, "</tbody></table>")?;
+    Ok(())
+}
+
+fn append_malformed_table<W: Write>(
+    out: &mut W,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+) -> io::Result<()> {
+    write!(
+        out,
+        "<table><thead><tr>\
+           <th scope='col'>Side</th>\
+           <th scope='col'>File</th>\
+           <th scope='col'>Line</th>\
+           <th scope='col'>Problem</th>\
+           <th scope='col'>Record</th>\
+         </tr></thead><tbody>"
+    )?;
+    for (side, t) in [("left", lt), ("right", rt)] {
+        for x in t.malformed.iter() {
+            write!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+                side,
+                Escaped(&x.file.display().to_string()),
+                x.line,
+                Escaped(&x.reason),
+                Escaped(&x.raw)
+            )?;
+        }
+    }
+    write!(out, "</tbody></table>")
+}
+
 pub fn write_html_diff<W: Write>(
     out: &mut W,
     config: &Config,
@@ -339,20 +570,49 @@ pub fn write_html_diff<W: Write>(
             rt.len()?,
             rt.key_columns.len() + rt.non_key_columns.len() + config.ignored_cols[1].len(),
         ),
+        lt.malformed.len() + rt.malformed.len(),
     )?;
 
     write!(out, "<hr>")?;
 
     write!(out, "<div id='l' class='x'><h3>Only in left</h3>")?;
-    append_only_table(&mut out, lt, interner, &comparison.only_indices[0])?;
+    append_only_table(&mut out, config, lt, interner, &comparison.only_indices[0])?;
     write!(out, "</div>")?;
 
     write!(out, "<div id='r' class='x'><h3>Only in right</h3>")?;
-    append_only_table(&mut out, rt, interner, &comparison.only_indices[1])?;
+    append_only_table(&mut out, config, rt, interner, &comparison.only_indices[1])?;
     write!(out, "</div>")?;
 
+    if !comparison.duplicate_keys.is_empty() {
+        write!(
+            out,
+            "<div id='k' class='x'><h3>Duplicate keys ({})</h3>",
+            match config.duplicate_keys {
+                DuplicateKeys::Error => unreachable!("comparison should have failed"),
+                DuplicateKeys::First => "only the first row of each key was compared",
+                DuplicateKeys::Multiset => "rows of each key were compared as a multiset",
+            }
+        )?;
+        append_duplicate_keys_table(&mut out, config, (lt, rt), interner, comparison)?;
+        write!(out, "</div>")?;
+    }
+
+    if !lt.malformed.is_empty() || !rt.malformed.is_empty() {
+        write!(
+            out,
+            "<div id='m' class='x'><h3>Malformed records ({})</h3>",
+            match config.malformed {
+                Malformed::Error => unreachable!("reading should have failed"),
+                Malformed::Skip => "skipped",
+                Malformed::Pad => "padded with nulls or truncated",
+            }
+        )?;
+        append_malformed_table(&mut out, (lt, rt))?;
+        write!(out, "</div>")?;
+    }
+
     write!(out, "<div id='d' class='i'><h3>Diffs</h3>")?;
-    append_diff_table(&mut out, (lt, rt), interner, comparison)?;
+    append_diff_table(&mut out, config, (lt, rt), interner, comparison)?;
     write!(out, "</div>")?;
 
     write!(out, "</body></html>")?;
@@ -0,0 +1,300 @@
+use crate::arrow_reader::{self, ArrowReader};
+use crate::csv_reader::{CsvDialect, CsvReader};
+use crate::fixed_width_reader::{FixedWidthReader, Layout};
+use crate::jsonl_reader::JsonLinesReader;
+use crate::multi_reader::MultiReader;
+use crate::parquet_reader::ParquetReader;
+use crate::sqlite_reader::{self, SqliteReader};
+use crate::sym::{Interner, Sym};
+use crate::xlsx_reader::{CellFormat, XlsxReader};
+use anyhow::bail;
+use bitvec::vec::BitVec;
+use bzip2::read::MultiBzDecoder;
+use clap::ValueEnum;
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
+use itertools::Itertools;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Chain, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    fn from_magic(buf: &[u8]) -> Option<Self> {
+        if buf.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if buf.starts_with(b"BZh") && buf.get(3).is_some_and(|x| (b'1'..=b'9').contains(x)) {
+            // `BZh` followed by the block size
+            Some(Self::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    // unlike the others, the bzip2 magic bytes are printable and may start a text file
+    fn is_ambiguous(self) -> bool {
+        matches!(self, Self::Bzip2)
+    }
+
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "bz2" => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+}
+
+/// Path standing for stdin.
+pub const STDIN: &str = "-";
+
+/// Returns whether `path` is a regular file, which unlike stdin or a pipe can be read more than
+/// once and seeked.
+pub fn is_regular_file(path: &Path) -> bool {
+    path != Path::new(STDIN) && fs::metadata(path).is_ok_and(|x| x.is_file())
+}
+
+// a reader with its first bytes put back
+type Peeked<R> = Chain<Cursor<Vec<u8>>, R>;
+
+// reads up to `n` bytes from the start of `rdr` without consuming them, reads from pipes may
+// return fewer bytes than asked for
+fn peek<R: Read>(mut rdr: R, n: usize) -> io::Result<(Vec<u8>, Peeked<R>)> {
+    let mut buf = Vec::with_capacity(n);
+    (&mut rdr).take(n as u64).read_to_end(&mut buf)?;
+    Ok((buf.clone(), Cursor::new(buf).chain(rdr)))
+}
+
+/// Opens a file, or stdin for `-`, for reading, transparently decompressing it if it is gzip, zstd
+/// or bzip2 compressed. The compression is detected from the magic bytes at the start of the
+/// file, or from its extension if they are inconclusive. Magic bytes which may as well be text are
+/// ignored for `.csv`, `.tsv` and `.txt` files.
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let rdr: Box<dyn Read> = match path == Path::new(STDIN) {
+        true => Box::new(io::stdin()),
+        false => Box::new(File::open(path)?),
+    };
+    let (magic, rdr) = peek(rdr, 4)?;
+    let rdr = BufReader::new(rdr);
+    let compression = match Compression::from_magic(&magic) {
+        // a file named as uncompressed text is taken at its word
+        Some(x) if x.is_ambiguous() && matches!(extension(path), "csv" | "tsv" | "txt") => None,
+        Some(x) => Some(x),
+        None => Compression::from_extension(path),
+    };
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(rdr)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(rdr)?),
+        Some(Compression::Bzip2) => Box::new(MultiBzDecoder::new(rdr)),
+        None => Box::new(rdr),
+    })
+}
+
+const BOMS: [&[u8]; 3] = [b"\xef\xbb\xbf", b"\xff\xfe", b"\xfe\xff"];
+
+/// Opens a text file like [`open`], transcoding it to UTF-8 from `encoding` if given. A byte order
+/// mark at the start of the file is stripped, and overrides `encoding`.
+pub fn open_text(path: &Path, encoding: Option<&'static Encoding>) -> io::Result<Box<dyn Read>> {
+    let (magic, rdr) = peek(open(path)?, 3)?;
+    if encoding.is_none() && !BOMS.iter().any(|bom| magic.starts_with(bom)) {
+        return Ok(Box::new(rdr)); // UTF-8, read as is
+    }
+    Ok(Box::new(
+        DecodeReaderBytesBuilder::new()
+            .encoding(encoding)
+            .bom_override(true)
+            .strip_bom(true)
+            .utf8_passthru(true)
+            .build(rdr),
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Delimited text, optionally compressed
+    Csv,
+    Parquet,
+    /// Arrow IPC file (Feather v2) or stream
+    Arrow,
+    /// JSON Lines, optionally compressed
+    Jsonl,
+    /// Excel or OpenDocument spreadsheet
+    Xlsx,
+    /// Fixed-width text, optionally compressed, with the column layout given separately
+    Fixed,
+    /// SQLite table or query, given as `sqlite:///PATH?table=NAME` or `sqlite:///PATH?query=SQL`
+    Sqlite,
+}
+
+fn extension(path: &Path) -> &str {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default()
+}
+
+impl InputFormat {
+    /// Guesses the format of a file from its extension, falling back to its magic bytes.
+    pub fn detect(path: &Path) -> io::Result<Self> {
+        if sqlite_reader::is_sqlite_url(path) {
+            return Ok(Self::Sqlite);
+        }
+        // text formats may be compressed, e.g. `data.jsonl.gz`
+        let inner_path = match Compression::from_extension(path) {
+            Some(_) => Path::new(path.file_stem().unwrap_or_default()),
+            None => path,
+        };
+        if let "jsonl" | "ndjson" = extension(inner_path) {
+            return Ok(Self::Jsonl);
+        }
+        match extension(path) {
+            "parquet" | "pq" => return Ok(Self::Parquet),
+            "arrow" | "arrows" | "feather" | "ipc" => return Ok(Self::Arrow),
+            "xlsx" | "xlsm" | "xlsb" | "xls" | "ods" => return Ok(Self::Xlsx),
+            _ => (),
+        }
+        // sniffing stdin or a pipe would consume its contents
+        if !is_regular_file(path) {
+            return Ok(Self::Csv);
+        }
+        let mut magic = [0u8; 6];
+        let n = File::open(path)?.read(&mut magic)?;
+        Ok(if magic[..n].starts_with(b"PAR1") {
+            Self::Parquet
+        } else if arrow_reader::is_arrow_file(&magic[..n]) {
+            Self::Arrow
+        } else {
+            Self::Csv
+        })
+    }
+}
+
+/// Per-file options for reading an input.
+#[derive(Clone)]
+pub struct InputOptions {
+    pub format: InputFormat,
+    pub dialect: CsvDialect,                 // only for csv
+    pub columns: Option<Vec<String>>,        // only for csv, names of the columns without a header
+    pub encoding: Option<&'static Encoding>, // only for text formats, UTF-8 by default
+    pub json_sample: Option<usize>,          // only for jsonl
+    pub sheet: Option<String>,               // only for xlsx
+    pub header_row: usize,                   // only for xlsx
+    pub cell_format: CellFormat,             // only for xlsx
+    pub layout: Option<Layout>,              // only for fixed
+    pub source_column: Option<String>, // name of a column holding the file each row was read from
+}
+
+/// A record that did not fit the headers of its file, which was skipped or padded as per
+/// `--on-malformed`.
+#[derive(Serialize)]
+pub struct MalformedRecord {
+    pub file: PathBuf,
+    pub line: u64,
+    pub reason: String,
+    pub raw: String, // the record as in the file, give or take quoting
+}
+
+/// A source of records to be read into a table.
+pub trait Reader {
+    /// Column names, in the order in which they appear in the input.
+    fn headers(&self) -> &[String];
+
+    /// Reads the next record, interning the values of the columns selected by `mask` (one bit per
+    /// header) into `record`. Returns `false` once there are no more records.
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool>;
+
+    /// Takes the malformed records read so far, see [`MalformedRecord`].
+    fn take_malformed(&mut self) -> Vec<MalformedRecord> {
+        Vec::new()
+    }
+}
+
+/// Expands a glob pattern, e.g. `out/*.csv`, or a directory into the files to be read, sorted by
+/// name. Files of a directory whose names start with `.` or `_` (e.g. `_SUCCESS` markers) are
+/// skipped. Other paths, including existing files whose names contain glob characters, are
+/// returned as is.
+pub fn expand(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = if path.is_dir() {
+        fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .filter_ok(|x| {
+                x.is_file()
+                    && !x
+                        .file_name()
+                        .and_then(|x| x.to_str())
+                        .is_some_and(|x| x.starts_with(['.', '_']))
+            })
+            .collect::<io::Result<Vec<_>>>()?
+    } else if path.exists() || sqlite_reader::is_sqlite_url(path) {
+        return Ok(vec![path.to_owned()]);
+    } else {
+        match path.to_str() {
+            Some(pattern) if pattern.contains(['*', '?', '[']) => glob::glob(pattern)?
+                .filter_ok(|x| x.is_file())
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Ok(vec![path.to_owned()]),
+        }
+    };
+    if paths.is_empty() {
+        bail!("no files found");
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Opens the files a path expanded to, see [`expand`].
+pub fn open_reader(paths: &[PathBuf], options: &InputOptions) -> anyhow::Result<Box<dyn Reader>> {
+    Ok(match (paths, &options.source_column) {
+        ([path], None) => open_file_reader(path, options)?,
+        _ => Box::new(MultiReader::new(paths.to_vec(), options)?),
+    })
+}
+
+pub fn open_file_reader(path: &Path, options: &InputOptions) -> anyhow::Result<Box<dyn Reader>> {
+    if let InputFormat::Parquet | InputFormat::Xlsx = options.format {
+        if !is_regular_file(path) {
+            bail!("Parquet and spreadsheet files cannot be read from stdin or a pipe");
+        }
+    }
+    Ok(match options.format {
+        InputFormat::Csv => Box::new(CsvReader::new(
+            path,
+            &options.dialect,
+            options.columns.as_deref(),
+            options.encoding,
+        )?),
+        InputFormat::Parquet => Box::new(ParquetReader::new(path)?),
+        InputFormat::Arrow => Box::new(ArrowReader::new(path)?),
+        InputFormat::Jsonl => Box::new(JsonLinesReader::new(
+            path,
+            options.json_sample,
+            options.encoding,
+        )?),
+        InputFormat::Xlsx => Box::new(XlsxReader::new(
+            path,
+            options.sheet.as_deref(),
+            options.header_row,
+            &options.cell_format,
+        )?),
+        InputFormat::Fixed => match &options.layout {
+            Some(layout) => Box::new(FixedWidthReader::new(path, layout, options.encoding)?),
+            None => bail!("a layout is required for fixed-width files"),
+        },
+        InputFormat::Sqlite => Box::new(SqliteReader::new(path)?),
+    })
+}
@@ -0,0 +1,273 @@
+use crate::comparator::{numeric_delta, Comparator};
+use crate::sym::{Sym, NULL};
+use crate::table::{get_aligned_non_key_cols_mmaps, resolve, Comparison, SlicesFromMmaps};
+use crate::{Config, Interner, KeyedTable};
+use indexmap::IndexMap;
+use log::info;
+use serde::Serialize;
+use std::io::{BufWriter, Write};
+
+#[derive(Serialize)]
+struct Stats {
+    left_rows: usize,
+    right_rows: usize,
+    match_rows: usize,
+    diff_rows: usize,
+    match_cells: usize,
+    diff_cells: usize,
+    null_diff_cells: usize, // diff cells with a null on only one side
+    left_only_rows: usize,
+    right_only_rows: usize,
+    duplicate_keys: usize,
+    left_malformed_records: usize,
+    right_malformed_records: usize,
+}
+
+#[derive(Serialize)]
+struct OnlyRow<'a> {
+    key: IndexMap<&'a str, Option<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>, // only with a source column
+    values: IndexMap<&'a str, Option<&'a str>>,
+}
+
+#[derive(Serialize)]
+struct DiffRow<'a> {
+    key: IndexMap<&'a str, Option<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    left_source: Option<&'a str>, // only with a source column
+    #[serde(skip_serializing_if = "Option::is_none")]
+    right_source: Option<&'a str>,
+    cells: Vec<DiffCell<'a>>,
+}
+
+#[derive(Serialize)]
+struct DiffCell<'a> {
+    column: &'a str,
+    left: Option<&'a str>,
+    right: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<String>, // only for numerically compared columns
+}
+
+#[derive(Serialize)]
+struct DuplicateKeyRow<'a> {
+    key: IndexMap<&'a str, Option<&'a str>>,
+    left_count: usize,
+    right_count: usize,
+}
+
+// missing values are written as JSON nulls
+fn value(interner: &Interner, value: usize) -> Option<&str> {
+    Some(resolve(interner, value)).filter(|&x| x != NULL)
+}
+
+// column name -> value of the row at `idx`
+fn row_values<'a>(
+    cols: &[Sym],
+    slices: &[&[usize]],
+    idx: usize,
+    interner: &'a Interner,
+) -> IndexMap<&'a str, Option<&'a str>> {
+    cols.iter()
+        .zip(slices.iter())
+        .map(|(&col, slice)| {
+            (
+                interner.resolve(col).unwrap(),
+                value(interner, unsafe { *slice.get_unchecked(idx) }),
+            )
+        })
+        .collect()
+}
+
+// file the row at `idx` was read from, if there is a source column
+fn source_value<'a>(slices: &[&[usize]], idx: usize, interner: &'a Interner) -> Option<&'a str> {
+    slices
+        .first()
+        .map(|slice| resolve(interner, unsafe { *slice.get_unchecked(idx) }))
+}
+
+// writes each item as an element of a JSON array, without collecting them first
+fn write_array<W: Write, T: Serialize>(
+    out: &mut W,
+    items: impl Iterator<Item = T>,
+) -> anyhow::Result<()> {
+    write!(out, "[")?;
+    for (idx, item) in items.enumerate() {
+        if idx > 0 {
+            write!(out, ",")?;
+        }
+        serde_json::to_writer(&mut *out, &item)?;
+    }
+    write!(out, "]")?;
+    Ok(())
+}
+
+fn append_only_rows<W: Write>(
+    out: &mut W,
+    t: &KeyedTable,
+    interner: &Interner,
+    t_only_indices: &[usize],
+) -> anyhow::Result<()> {
+    let key_cols_mmaps = t.get_cols_mmaps(true)?;
+    let key_cols_slices = key_cols_mmaps.to_slices()?;
+
+    let non_key_cols_mmaps = t.get_cols_mmaps(false)?;
+    let non_key_cols_slices = non_key_cols_mmaps.to_slices()?;
+
+    let source_col_mmaps = t.get_source_col_mmaps()?;
+    let source_col_slices = source_col_mmaps.to_slices()?;
+
+    write_array(
+        out,
+        t_only_indices.iter().map(|&idx| OnlyRow {
+            key: row_values(&t.key_columns, &key_cols_slices, idx, interner),
+            source: source_value(&source_col_slices, idx, interner),
+            values: row_values(&t.non_key_columns, &non_key_cols_slices, idx, interner),
+        }),
+    )
+}
+
+fn append_diff_rows<W: Write>(
+    out: &mut W,
+    config: &Config,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+) -> anyhow::Result<()> {
+    let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
+    let lt_key_cols_slices = lt_key_cols_mmaps.to_slices()?;
+
+    let [lt_non_key_cols_mmaps, rt_non_key_cols_mmaps] = get_aligned_non_key_cols_mmaps(lt, rt)?;
+    let lt_non_key_cols_slices = lt_non_key_cols_mmaps.to_slices()?;
+    let rt_non_key_cols_slices = rt_non_key_cols_mmaps.to_slices()?;
+
+    let lt_source_col_mmaps = lt.get_source_col_mmaps()?;
+    let lt_source_col_slices = lt_source_col_mmaps.to_slices()?;
+    let rt_source_col_mmaps = rt.get_source_col_mmaps()?;
+    let rt_source_col_slices = rt_source_col_mmaps.to_slices()?;
+
+    let rows = comparison.common_indices[0]
+        .iter()
+        .zip(comparison.common_indices[1].iter())
+        .enumerate()
+        .map(|(idx, (&lt_idx, &rt_idx))| DiffRow {
+            key: row_values(&lt.key_columns, &lt_key_cols_slices, lt_idx, interner),
+            left_source: source_value(&lt_source_col_slices, lt_idx, interner),
+            right_source: source_value(&rt_source_col_slices, rt_idx, interner),
+            cells: lt
+                .non_key_columns
+                .iter()
+                .zip(lt_non_key_cols_slices.iter())
+                .zip(rt_non_key_cols_slices.iter())
+                .zip(comparison.tt.iter())
+                .filter(|(_, col_match)| !col_match[idx])
+                .map(|(((&col, lt_col), rt_col), _)| {
+                    let left = value(interner, unsafe { *lt_col.get_unchecked(lt_idx) });
+                    let right = value(interner, unsafe { *rt_col.get_unchecked(rt_idx) });
+                    DiffCell {
+                        column: interner.resolve(col).unwrap(),
+                        left,
+                        right,
+                        delta: match (config.comparators.get(&col), left, right) {
+                            (Some(Comparator::Numeric(_)), Some(left), Some(right)) => {
+                                numeric_delta(left, right)
+                            }
+                            _ => None,
+                        },
+                    }
+                })
+                .collect(),
+        });
+    write_array(out, rows)
+}
+
+fn append_duplicate_keys<W: Write>(
+    out: &mut W,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+) -> anyhow::Result<()> {
+    let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
+    let rt_key_cols_mmaps = rt.get_cols_mmaps(true)?;
+    let key_cols_slices = [
+        lt_key_cols_mmaps.to_slices()?,
+        rt_key_cols_mmaps.to_slices()?,
+    ];
+
+    let rows = comparison.duplicate_keys.iter().map(|dup| {
+        let (side, idx) = match dup.indices {
+            [Some(idx), _] => (0, idx),
+            [None, Some(idx)] => (1, idx),
+            [None, None] => unreachable!("duplicate key should be present in some table"),
+        };
+        DuplicateKeyRow {
+            key: row_values(&lt.key_columns, &key_cols_slices[side], idx, interner),
+            left_count: dup.counts[0],
+            right_count: dup.counts[1],
+        }
+    });
+    write_array(out, rows)
+}
+
+pub fn write_json_diff<W: Write>(
+    out: &mut W,
+    config: &Config,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+) -> anyhow::Result<()> {
+    let mut out = BufWriter::new(out);
+    info!("generating comparison json");
+
+    let names = |cols: &[Sym]| {
+        cols.iter()
+            .map(|&x| interner.resolve(x).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    write!(out, "{{\"left_file\":")?;
+    serde_json::to_writer(&mut out, &config.files[0])?;
+    write!(out, ",\"right_file\":")?;
+    serde_json::to_writer(&mut out, &config.files[1])?;
+    write!(out, ",\"key_columns\":")?;
+    serde_json::to_writer(&mut out, &names(&lt.key_columns))?;
+    write!(out, ",\"columns\":")?;
+    serde_json::to_writer(&mut out, &names(&lt.non_key_columns))?;
+
+    write!(out, ",\"stats\":")?;
+    serde_json::to_writer(
+        &mut out,
+        &Stats {
+            left_rows: lt.len()?,
+            right_rows: rt.len()?,
+            match_rows: comparison.match_row_count,
+            diff_rows: comparison.diff_row_count,
+            match_cells: comparison.match_cell_count,
+            diff_cells: comparison.diff_cell_count,
+            null_diff_cells: comparison.null_diff_cell_count,
+            left_only_rows: comparison.only_indices[0].len(),
+            right_only_rows: comparison.only_indices[1].len(),
+            duplicate_keys: comparison.duplicate_keys.len(),
+            left_malformed_records: lt.malformed.len(),
+            right_malformed_records: rt.malformed.len(),
+        },
+    )?;
+
+    write!(out, ",\"left_only\":")?;
+    append_only_rows(&mut out, lt, interner, &comparison.only_indices[0])?;
+    write!(out, ",\"right_only\":")?;
+    append_only_rows(&mut out, rt, interner, &comparison.only_indices[1])?;
+    write!(out, ",\"duplicate_keys\":")?;
+    append_duplicate_keys(&mut out, (lt, rt), interner, comparison)?;
+    write!(out, ",\"left_malformed\":")?;
+    serde_json::to_writer(&mut out, &lt.malformed)?;
+    write!(out, ",\"right_malformed\":")?;
+    serde_json::to_writer(&mut out, &rt.malformed)?;
+    write!(out, ",\"diffs\":")?;
+    append_diff_rows(&mut out, config, (lt, rt), interner, comparison)?;
+
+    writeln!(out, "}}")?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use crate::input::{self, Reader};
+use crate::sym::{Interner, Sym, NULL};
+use anyhow::{bail, Context};
+use bitvec::vec::BitVec;
+use encoding_rs::Encoding;
+use indexmap::IndexMap;
+use log::warn;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+// writes `value` as JSON with the keys of objects sorted, so that equal values render the same
+// regardless of key order in the input
+fn write_canonical(out: &mut String, value: &Value) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                write_canonical(out, item);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|&(k, _)| k);
+            out.push('{');
+            for (idx, (k, v)) in entries.into_iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                write!(out, "{}:", Value::from(k.as_str())).unwrap();
+                write_canonical(out, v);
+            }
+            out.push('}');
+        }
+        _ => write!(out, "{}", value).unwrap(),
+    }
+}
+
+// calls `f` with the dotted name and value of each leaf of `object`, nested objects being
+// flattened and arrays being leaves
+fn flatten<'a>(
+    prefix: &str,
+    object: &'a Map<String, Value>,
+    f: &mut impl FnMut(String, &'a Value),
+) {
+    for (k, v) in object.iter() {
+        let name = if prefix.is_empty() {
+            k.trim().to_owned()
+        } else {
+            format!("{}.{}", prefix, k.trim())
+        };
+        match v {
+            Value::Object(inner) => flatten(&name, inner, f),
+            _ => f(name, v),
+        }
+    }
+}
+
+// reads the next non-blank line as a JSON object, returns `None` at the end of the input
+fn read_object(
+    rdr: &mut impl BufRead,
+    line: &mut String,
+    line_no: &mut usize,
+) -> anyhow::Result<Option<Map<String, Value>>> {
+    loop {
+        line.clear();
+        if rdr.read_line(line)? == 0 {
+            return Ok(None);
+        }
+        *line_no += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        return match serde_json::from_str(line).with_context(|| format!("line {}", line_no))? {
+            Value::Object(object) => Ok(Some(object)),
+            _ => bail!("line {}: expected a JSON object", line_no),
+        };
+    }
+}
+
+/// Reads JSON Lines, one object per line. Nested objects are flattened into dotted column names
+/// (`address.city`), arrays are rendered as canonical JSON and fields missing from a record are
+/// read as [`NULL`], as are JSON nulls.
+pub struct JsonLinesReader {
+    rdr: Box<dyn BufRead>,
+    headers: Vec<String>,
+    positions: IndexMap<String, usize>, // header -> position
+    line: String,
+    line_no: usize,
+    values: Vec<Option<Sym>>, // values of the current record, by header position
+    unseen: HashSet<String>,  // fields not in the scanned records, warned about once
+    buf: String,
+}
+
+impl JsonLinesReader {
+    /// Columns are discovered by scanning the first `sample` records, or all of them.
+    pub fn new(
+        path: &Path,
+        sample: Option<usize>,
+        encoding: Option<&'static Encoding>,
+    ) -> anyhow::Result<Self> {
+        let mut positions = IndexMap::new();
+        let mut rdr = BufReader::new(input::open_text(path, encoding)?);
+        // stdin or a pipe cannot be read again, so the scanned lines are kept to be read first
+        let reopen = input::is_regular_file(path);
+        let mut scanned = String::new();
+        let (mut line, mut line_no) = (String::new(), 0);
+        for _ in 0..sample.unwrap_or(usize::MAX) {
+            let prev_line_no = line_no;
+            let Some(object) = read_object(&mut rdr, &mut line, &mut line_no)? else {
+                break;
+            };
+            flatten("", &object, &mut |name, _| {
+                let len = positions.len();
+                positions.entry(name).or_insert(len);
+            });
+            if !reopen {
+                // blank lines are skipped, but count for line numbers
+                scanned.extend((prev_line_no + 1..line_no).map(|_| '\n'));
+                scanned.push_str(&line);
+            }
+        }
+
+        let rdr: Box<dyn BufRead> = match reopen {
+            true => Box::new(BufReader::new(input::open_text(path, encoding)?)),
+            false => Box::new(Cursor::new(scanned).chain(rdr)),
+        };
+        Ok(Self {
+            rdr,
+            headers: positions.keys().cloned().collect(),
+            positions,
+            line,
+            line_no: 0,
+            values: Vec::new(),
+            unseen: HashSet::new(),
+            buf: String::new(),
+        })
+    }
+}
+
+impl Reader for JsonLinesReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        let object = match read_object(&mut self.rdr, &mut self.line, &mut self.line_no)? {
+            Some(object) => object,
+            None => return Ok(false),
+        };
+
+        self.values.clear();
+        self.values.resize(self.headers.len(), None);
+        flatten("", &object, &mut |name, value| {
+            let Some(&pos) = self.positions.get(&name) else {
+                // the field has no column, so it is not read
+                if !self.unseen.contains(&name) {
+                    warn!(
+                        "line {}: skipping field {} which was not seen in the records scanned for columns (see --json-sample)",
+                        self.line_no, name
+                    );
+                    self.unseen.insert(name);
+                }
+                return;
+            };
+            if !mask[pos] {
+                return;
+            }
+            self.values[pos] = Some(match value {
+                Value::Null => interner.get_or_intern_static(NULL),
+                Value::String(x) => interner.get_or_intern(x.trim()),
+                _ => {
+                    self.buf.clear();
+                    write_canonical(&mut self.buf, value);
+                    interner.get_or_intern(&self.buf)
+                }
+            });
+        });
+        record.clear();
+        record.extend(
+            self.values
+                .iter()
+                .zip(mask.iter())
+                .filter(|(_, selected)| **selected)
+                .map(|(x, _)| x.unwrap_or_else(|| interner.get_or_intern_static(NULL))),
+        );
+        Ok(true)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::sym::NULL;
+use crate::table::{get_aligned_non_key_cols_mmaps, resolve, Comparison, SlicesFromMmaps};
+use crate::{Interner, KeyedTable};
+use csv::Writer;
+use log::info;
+use std::io::Write;
+
+// missing values are written as empty fields
+fn value(interner: &Interner, value: usize) -> &str {
+    match resolve(interner, value) {
+        NULL => "",
+        x => x,
+    }
+}
+
+fn append_only_rows<W: Write>(
+    out: &mut Writer<W>,
+    t: &KeyedTable,
+    interner: &Interner,
+    t_only_indices: &[usize],
+    side: usize, // 0 for left, 1 for right
+) -> anyhow::Result<()> {
+    let key_cols_mmaps = t.get_cols_mmaps(true)?;
+    let key_cols_slices = key_cols_mmaps.to_slices()?;
+
+    let non_key_cols_mmaps = t.get_cols_mmaps(false)?;
+    let non_key_cols_slices = non_key_cols_mmaps.to_slices()?;
+
+    let source_col_mmaps = t.get_source_col_mmaps()?;
+    let source_col_slices = source_col_mmaps.to_slices()?;
+
+    for &idx in t_only_indices.iter() {
+        for (&col, non_key_col_slice) in t.non_key_columns.iter().zip(non_key_cols_slices.iter()) {
+            let val = value(interner, unsafe { *non_key_col_slice.get_unchecked(idx) });
+            for key_col_slice in key_cols_slices.iter() {
+                out.write_field(value(interner, unsafe {
+                    *key_col_slice.get_unchecked(idx)
+                }))?;
+            }
+            for source_col_slice in source_col_slices.iter() {
+                let mut sources = ["", ""];
+                sources[side] = value(interner, unsafe { *source_col_slice.get_unchecked(idx) });
+                out.write_field(sources[0])?;
+                out.write_field(sources[1])?;
+            }
+            out.write_field(interner.resolve(col).unwrap())?;
+            let mut vals = ["", ""];
+            vals[side] = val;
+            out.write_record([vals[0], vals[1], ["left-only", "right-only"][side]])?;
+        }
+    }
+    Ok(())
+}
+
+fn append_diff_rows<W: Write>(
+    out: &mut Writer<W>,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+) -> anyhow::Result<()> {
+    let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
+    let lt_key_cols_slices = lt_key_cols_mmaps.to_slices()?;
+
+    let [lt_non_key_cols_mmaps, rt_non_key_cols_mmaps] = get_aligned_non_key_cols_mmaps(lt, rt)?;
+    let lt_non_key_cols_slices = lt_non_key_cols_mmaps.to_slices()?;
+    let rt_non_key_cols_slices = rt_non_key_cols_mmaps.to_slices()?;
+
+    let lt_source_col_mmaps = lt.get_source_col_mmaps()?;
+    let lt_source_col_slices = lt_source_col_mmaps.to_slices()?;
+    let rt_source_col_mmaps = rt.get_source_col_mmaps()?;
+    let rt_source_col_slices = rt_source_col_mmaps.to_slices()?;
+
+    for (idx, (&lt_idx, &rt_idx)) in comparison.common_indices[0]
+        .iter()
+        .zip(comparison.common_indices[1].iter())
+        .enumerate()
+    {
+        for (((&col, lt_col), rt_col), col_match) in lt
+            .non_key_columns
+            .iter()
+            .zip(lt_non_key_cols_slices.iter())
+            .zip(rt_non_key_cols_slices.iter())
+            .zip(comparison.tt.iter())
+        {
+            if col_match[idx] {
+                continue;
+            }
+            for lt_key_col_slice in lt_key_cols_slices.iter() {
+                out.write_field(value(interner, unsafe {
+                    *lt_key_col_slice.get_unchecked(lt_idx)
+                }))?;
+            }
+            for (lt_source_col_slice, rt_source_col_slice) in
+                lt_source_col_slices.iter().zip(rt_source_col_slices.iter())
+            {
+                out.write_field(value(interner, unsafe {
+                    *lt_source_col_slice.get_unchecked(lt_idx)
+                }))?;
+                out.write_field(value(interner, unsafe {
+                    *rt_source_col_slice.get_unchecked(rt_idx)
+                }))?;
+            }
+            let (lt_val, rt_val) =
+                unsafe { (*lt_col.get_unchecked(lt_idx), *rt_col.get_unchecked(rt_idx)) };
+            out.write_record([
+                interner.resolve(col).unwrap(),
+                value(interner, lt_val),
+                value(interner, rt_val),
+                // nulls are written as empty fields, so tell them apart from empty strings
+                match resolve(interner, lt_val) == NULL || resolve(interner, rt_val) == NULL {
+                    true => "null-vs-value",
+                    false => "changed",
+                },
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the comparison as a long-format CSV having one row per differing cell: the key column
+/// values, the files both rows were read from (only with a source column), the column name, the
+/// left and right values and the kind of difference (`changed`, `null-vs-value` when only one side
+/// is null, `left-only` or `right-only`).
+///
+/// Rows are written straight from the column mmaps, so this works for diffs too large to be
+/// looked at in a HTML report.
+pub fn write_csv_diff<W: Write>(
+    out: &mut W,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+) -> anyhow::Result<()> {
+    let mut out = Writer::from_writer(out);
+    info!("generating comparison csv");
+
+    for &col in lt.key_columns.iter() {
+        out.write_field(interner.resolve(col).unwrap())?;
+    }
+    if lt.source_column.is_some() {
+        out.write_field("left_source")?;
+        out.write_field("right_source")?;
+    }
+    out.write_record(["column", "left", "right", "kind"])?;
+
+    append_diff_rows(&mut out, (lt, rt), interner, comparison)?;
+    append_only_rows(&mut out, lt, interner, &comparison.only_indices[0], 0)?;
+    append_only_rows(&mut out, rt, interner, &comparison.only_indices[1], 1)?;
+
+    out.flush()?;
+    Ok(())
+}
@@ -1,38 +1,132 @@
-use crate::cli::Cli;
+use crate::cli::{Cli, Format};
 use crate::config::Config;
 use crate::html::write_html_diff;
+use crate::json::write_json_diff;
+use crate::long_csv::write_csv_diff;
 use crate::sym::Interner;
 use crate::table::{compare_tables, KeyedTable};
+use crate::term::write_term_diff;
 use anyhow::Context;
 use clap::Parser;
-use log::info;
-use std::fs::{self, File};
+use log::{error, info};
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use tempfile::NamedTempFile;
 use url::Url;
 
+mod arrow_reader;
 mod cli;
 mod column;
+mod comparator;
 mod config;
+mod csv_reader;
+mod fixed_width_reader;
 mod html;
+mod input;
+mod json;
+mod jsonl_reader;
+mod long_csv;
+mod multi_reader;
+mod parquet_reader;
+mod record_batch;
+mod rules;
+mod sqlite_reader;
 mod sym;
 mod table;
+mod term;
+mod threshold;
+mod xlsx_reader;
 
-fn main() -> anyhow::Result<()> {
+// exit codes, as with diff(1)
+const EXIT_SAME: u8 = 0;
+const EXIT_DIFFERENT: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+
+fn main() -> ExitCode {
     env_logger::builder().format_timestamp_micros().init();
+    match run() {
+        Ok(true) => ExitCode::from(EXIT_DIFFERENT),
+        Ok(false) => ExitCode::from(EXIT_SAME),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+// browsers are not opened on build servers, over ssh or when run from a script
+fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+        && io::stdout().is_terminal()
+        && env::var_os("CI").is_none()
+        && env::var_os("SSH_CONNECTION").is_none()
+}
+
+// The report is written to a temporary file next to the output file, which is only renamed over
+// the output file once the report is complete. An interrupted run thus never leaves a partial
+// report behind.
+fn create_tmp_out_file(out_file: &Path) -> io::Result<NamedTempFile> {
+    let dir = match out_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let f = tempfile::Builder::new()
+        .prefix(".tblcompare")
+        .tempfile_in(dir)?;
+    // temporary files are only accessible by the owner, keep the permissions of an older report
+    // or use the usual ones for a new file instead
+    match fs::metadata(out_file) {
+        Ok(metadata) => f.as_file().set_permissions(metadata.permissions())?,
+        #[cfg(unix)]
+        Err(_) => {
+            use std::os::unix::fs::PermissionsExt;
+            f.as_file()
+                .set_permissions(fs::Permissions::from_mode(0o644))?
+        }
+        #[cfg(not(unix))]
+        Err(_) => {}
+    }
+    Ok(f)
+}
+
+fn persist_out_file(f: NamedTempFile, out_file: &Path) -> io::Result<()> {
+    f.as_file().sync_all()?;
+    f.persist(out_file)?;
+    Ok(())
+}
+
+// returns whether the tables differ (or the thresholds for failing are breached)
+fn run() -> anyhow::Result<bool> {
     let mut interner = Interner::new();
-    let config = Config::try_from_cli(Cli::parse(), &mut interner)?;
-
-    // make sure we can open output file so that we are not left hanging later
-    let mut out_file = File::options()
-        .create(true)
-        .write(true)
-        .open(&config.out_file)
-        .with_context(|| format!("unable to open output file: {}", config.out_file.display()))?;
-
-    let mut lt = KeyedTable::from_csv(
-        &config.files[0],
-        config.delims[0],
+    let (config, [mut lrdr, mut rrdr]) = Config::try_from_cli(Cli::parse(), &mut interner)?;
+
+    // make sure we can create the output file so that we are not left hanging later
+    let to_stdout = config.out_file == Path::new("-");
+    let mut tmp_out_file = if to_stdout {
+        None
+    } else {
+        Some(create_tmp_out_file(&config.out_file).with_context(|| {
+            format!(
+                "unable to create output file: {}",
+                config.out_file.display()
+            )
+        })?)
+    };
+    let mut out_file: Box<dyn Write> = match tmp_out_file.as_mut() {
+        Some(f) => Box::new(f),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    info!("reading left file {}", config.files[0].display());
+    let mut lt = KeyedTable::from_reader(
+        lrdr.as_mut(),
         &config.common_cols,
         &config.key_cols,
+        config.source_col,
+        &config.null_tokens,
         &mut interner,
     )
     .with_context(|| {
@@ -42,11 +136,13 @@ fn main() -> anyhow::Result<()> {
         )
     })?;
 
-    let mut rt = KeyedTable::from_csv(
-        &config.files[1],
-        config.delims[1],
+    info!("reading right file {}", config.files[1].display());
+    let mut rt = KeyedTable::from_reader(
+        rrdr.as_mut(),
         &config.common_cols,
         &config.key_cols,
+        config.source_col,
+        &config.null_tokens,
         &mut interner,
     )
     .with_context(|| {
@@ -63,21 +159,61 @@ fn main() -> anyhow::Result<()> {
     info!("sorting right table");
     rt.sort_by_key_columns()?;
 
-    let result = compare_tables(&lt, &rt)?;
+    let result = compare_tables(&lt, &rt, &config, &interner)?;
+
+    match config.format {
+        Format::Html => write_html_diff(&mut out_file, &config, (&lt, &rt), &interner, &result)?,
+        Format::Json => write_json_diff(&mut out_file, &config, (&lt, &rt), &interner, &result)?,
+        Format::Csv => write_csv_diff(&mut out_file, (&lt, &rt), &interner, &result)?,
+        Format::Term => {
+            let color =
+                to_stdout && io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+            write_term_diff(
+                &mut out_file,
+                &config,
+                (&lt, &rt),
+                &interner,
+                &result,
+                color,
+            )?
+        }
+    }
 
-    write_html_diff(&mut out_file, &config, (&lt, &rt), &interner, &result)?;
+    drop(out_file);
+    if let Some(f) = tmp_out_file {
+        persist_out_file(f, &config.out_file).with_context(|| {
+            format!("unable to write output file: {}", config.out_file.display())
+        })?;
+    }
 
-    if webbrowser::open(
-        Url::from_file_path(fs::canonicalize(config.out_file)?)
-            .unwrap()
-            .as_str(),
-    )
-    .is_ok()
+    if config.format == Format::Html
+        && !to_stdout
+        && config.open_browser
+        && is_interactive()
+        && webbrowser::open(
+            Url::from_file_path(fs::canonicalize(&config.out_file)?)
+                .unwrap()
+                .as_str(),
+        )
+        .is_ok()
     {
         info!("opened results in web browser");
-    } else {
+    } else if !to_stdout {
         info!("wrote results to output file");
     }
 
-    Ok(())
+    if config.thresholds.is_empty() {
+        return Ok(result.diff_row_count > 0
+            || result.only_indices.iter().any(|x| !x.is_empty())
+            || config.ignored_cols.iter().any(|x| !x.is_empty()));
+    }
+
+    let mut breached = false;
+    for threshold in config.thresholds.iter() {
+        if threshold.is_breached(&config, (&lt, &rt), &result)? {
+            error!("threshold breached: {}", threshold);
+            breached = true;
+        }
+    }
+    Ok(breached)
 }
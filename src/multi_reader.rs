@@ -0,0 +1,108 @@
+use crate::input::{self, InputOptions, MalformedRecord, Reader};
+use crate::sym::{Interner, Sym};
+use anyhow::{bail, Context};
+use bitvec::vec::BitVec;
+use std::mem;
+use std::path::PathBuf;
+
+/// Reads several files with identical headers one after the other, e.g. the partitions of a
+/// dataset, optionally adding a column holding the file each record was read from.
+pub struct MultiReader {
+    paths: Vec<PathBuf>,
+    options: InputOptions,
+    rdr: Box<dyn Reader>,
+    idx: usize, // position of the file being read
+    headers: Vec<String>,
+    file_headers: usize,             // number of headers of the files themselves
+    mask: Option<BitVec>,            // `mask` of the files themselves, without the source column
+    source: Option<Sym>,             // source column value for the file being read
+    malformed: Vec<MalformedRecord>, // of the files already read
+}
+
+impl MultiReader {
+    pub fn new(paths: Vec<PathBuf>, options: &InputOptions) -> anyhow::Result<Self> {
+        let rdr = input::open_file_reader(&paths[0], options)
+            .with_context(|| format!("Failed to read {:?}", paths[0].display()))?;
+        let mut headers = rdr.headers().to_vec();
+        let file_headers = headers.len();
+        if let Some(name) = options.source_column.as_ref() {
+            if headers.contains(name) {
+                bail!("source column {} is already present in the file", name);
+            }
+            headers.push(name.clone());
+        }
+        Ok(Self {
+            paths,
+            options: options.clone(),
+            rdr,
+            idx: 0,
+            headers,
+            file_headers,
+            mask: None,
+            source: None,
+            malformed: Vec::new(),
+        })
+    }
+
+    // opens the next file, returns `false` if there are no more files
+    fn next_file(&mut self) -> anyhow::Result<bool> {
+        if self.idx + 1 == self.paths.len() {
+            return Ok(false);
+        }
+        self.idx += 1;
+        self.malformed.extend(self.rdr.take_malformed());
+        let path = &self.paths[self.idx];
+        self.rdr = input::open_file_reader(path, &self.options)
+            .with_context(|| format!("Failed to read {:?}", path.display()))?;
+        if self.rdr.headers() != &self.headers[..self.file_headers] {
+            bail!(
+                "columns of {:?} differ from those of {:?}",
+                path.display(),
+                self.paths[0].display()
+            );
+        }
+        self.source = None;
+        Ok(true)
+    }
+}
+
+impl Reader for MultiReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        let file_headers = self.file_headers;
+        if self.mask.is_none() {
+            self.mask = Some(mask[..file_headers].to_bitvec());
+        }
+        while !self
+            .rdr
+            .read_record(self.mask.as_ref().unwrap(), interner, record)?
+        {
+            if !self.next_file()? {
+                return Ok(false);
+            }
+        }
+        // the source column comes last
+        if mask.len() > file_headers && mask[file_headers] {
+            let path = &self.paths[self.idx];
+            let source = *self
+                .source
+                .get_or_insert_with(|| interner.get_or_intern(path.to_string_lossy()));
+            record.push(source);
+        }
+        Ok(true)
+    }
+
+    fn take_malformed(&mut self) -> Vec<MalformedRecord> {
+        let mut malformed = mem::take(&mut self.malformed);
+        malformed.extend(self.rdr.take_malformed());
+        malformed
+    }
+}
@@ -0,0 +1,61 @@
+use crate::input::Reader;
+use crate::record_batch::BatchRows;
+use crate::sym::{Interner, Sym};
+use bitvec::vec::BitVec;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ProjectionMask;
+use std::fs::File;
+use std::path::Path;
+
+pub struct ParquetReader {
+    // only the schema is read until the first record is, which decides the columns to read
+    builder: Option<ParquetRecordBatchReaderBuilder<File>>,
+    rdr: Option<ParquetRecordBatchReader>,
+    headers: Vec<String>,
+    rows: BatchRows,
+}
+
+impl ParquetReader {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+        let headers = builder
+            .schema()
+            .fields()
+            .iter()
+            .map(|x| x.name().trim().to_owned())
+            .collect();
+        Ok(Self {
+            builder: Some(builder),
+            rdr: None,
+            headers,
+            rows: BatchRows::default(),
+        })
+    }
+}
+
+impl Reader for ParquetReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        if let Some(builder) = self.builder.take() {
+            // top-level arrow fields are the roots of the parquet schema
+            let projection = ProjectionMask::roots(builder.parquet_schema(), mask.iter_ones());
+            self.rdr = Some(builder.with_projection(projection).build()?);
+        }
+        let rdr = self.rdr.as_mut().expect("reader should have been built");
+        while !self.rows.next_row(record) {
+            match rdr.next() {
+                Some(batch) => self.rows = BatchRows::new(&batch?, interner)?,
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
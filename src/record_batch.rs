@@ -0,0 +1,55 @@
+use crate::sym::{Interner, Sym, NULL};
+use arrow_array::RecordBatch;
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use std::fmt::Write;
+
+/// Values of an Arrow record batch, interned column by column and handed out row by row.
+///
+/// Values are rendered with Arrow's display formatting (e.g. timestamps as ISO 8601 and decimals
+/// with their scale), and nulls are interned as [`NULL`].
+#[derive(Default)]
+pub struct BatchRows {
+    columns: Vec<Vec<Sym>>,
+    len: usize,
+    pos: usize,
+}
+
+impl BatchRows {
+    pub fn new(batch: &RecordBatch, interner: &mut Interner) -> anyhow::Result<Self> {
+        let options = FormatOptions::default();
+        let mut buf = String::new();
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|array| {
+                let formatter = ArrayFormatter::try_new(array.as_ref(), &options)?;
+                (0..array.len())
+                    .map(|idx| {
+                        if array.is_null(idx) {
+                            return Ok(interner.get_or_intern_static(NULL));
+                        }
+                        buf.clear();
+                        write!(buf, "{}", formatter.value(idx))?;
+                        Ok(interner.get_or_intern(buf.trim()))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            columns,
+            len: batch.num_rows(),
+            pos: 0,
+        })
+    }
+
+    /// Copies the next row into `record`, returns `false` once all rows have been read.
+    pub fn next_row(&mut self, record: &mut Vec<Sym>) -> bool {
+        if self.pos >= self.len {
+            return false;
+        }
+        record.clear();
+        record.extend(self.columns.iter().map(|col| col[self.pos]));
+        self.pos += 1;
+        true
+    }
+}
@@ -0,0 +1,125 @@
+use crate::comparator::{Comparator, Tolerance, DEFAULT_DATE_FORMATS};
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Per-column comparison rules, read from a TOML file such as:
+///
+/// ```toml
+/// [columns.price]
+/// comparator = "numeric"
+/// abs = 0.01
+///
+/// [columns.name]
+/// comparator = "case-insensitive"
+/// display-name = "Customer name"
+///
+/// [columns.created]
+/// comparator = "date"
+/// formats = ["%d/%m/%Y"]
+///
+/// [columns.phone]
+/// comparator = "regex"
+/// pattern = "[^0-9]"
+/// replacement = ""
+///
+/// [columns.load_ts]
+/// ignore = true
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(default)]
+    pub columns: IndexMap<String, ColumnRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ColumnRule {
+    #[serde(default)]
+    comparator: ComparatorKind,
+    abs: Option<f64>,
+    rel: Option<f64>,
+    formats: Option<Vec<String>>,
+    pattern: Option<String>,
+    #[serde(default)]
+    replacement: String,
+    #[serde(default)]
+    pub ignore: bool,
+    pub display_name: Option<String>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum ComparatorKind {
+    #[default]
+    Exact,
+    CaseInsensitive,
+    Numeric,
+    Date,
+    Regex,
+}
+
+impl Rules {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let rules = fs::read_to_string(path)?;
+        Ok(toml::from_str(&rules)?)
+    }
+}
+
+impl ColumnRule {
+    /// Comparator for the column, `None` if it is to be compared exactly.
+    pub fn comparator(&self, col: &str) -> Result<Option<Comparator>> {
+        let unexpected = |field: &str, value: bool| -> Result<()> {
+            if value {
+                bail!("column {}: {} is not used by this comparator", col, field);
+            }
+            Ok(())
+        };
+        if self.comparator != ComparatorKind::Numeric {
+            unexpected("abs", self.abs.is_some())?;
+            unexpected("rel", self.rel.is_some())?;
+        }
+        if self.comparator != ComparatorKind::Date {
+            unexpected("formats", self.formats.is_some())?;
+        }
+        if self.comparator != ComparatorKind::Regex {
+            unexpected("pattern", self.pattern.is_some())?;
+            unexpected("replacement", !self.replacement.is_empty())?;
+        }
+
+        Ok(match self.comparator {
+            ComparatorKind::Exact => None,
+            ComparatorKind::CaseInsensitive => Some(Comparator::CaseInsensitive),
+            ComparatorKind::Numeric => {
+                let tolerance = Tolerance {
+                    abs: self.abs.unwrap_or_default(),
+                    rel: self.rel.unwrap_or_default(),
+                };
+                if !(tolerance.abs >= 0.0 && tolerance.rel >= 0.0) {
+                    bail!("column {}: tolerances cannot be negative", col);
+                }
+                Some(Comparator::Numeric(tolerance))
+            }
+            ComparatorKind::Date => Some(Comparator::Date(match &self.formats {
+                Some(formats) if formats.is_empty() => {
+                    bail!("column {}: formats cannot be empty", col)
+                }
+                Some(formats) => formats.clone(),
+                None => DEFAULT_DATE_FORMATS.map(String::from).to_vec(),
+            })),
+            ComparatorKind::Regex => {
+                let pattern = self
+                    .pattern
+                    .as_deref()
+                    .with_context(|| format!("column {}: missing pattern", col))?;
+                let re = Regex::new(pattern)
+                    .with_context(|| format!("column {}: invalid pattern", col))?;
+                Some(Comparator::Regex(re, self.replacement.clone()))
+            }
+        })
+    }
+}
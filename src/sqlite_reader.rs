@@ -0,0 +1,162 @@
+use crate::input::Reader;
+use crate::sym::{Interner, Sym, NULL};
+use anyhow::{bail, Context};
+use bitvec::vec::BitVec;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::fmt::Write;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::vec;
+
+const BATCH_SIZE: usize = 1024;
+
+// rows sent from the querying thread, `None` values being nulls
+type Batch = Vec<Vec<Option<String>>>;
+
+/// Returns whether `path` is a SQLite source, i.e. of the form `sqlite:///data.db?table=orders` or
+/// `sqlite:///data.db?query=SELECT ...`.
+pub fn is_sqlite_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|x| x.starts_with("sqlite:"))
+}
+
+// returns the database path and the query of a SQLite source; as with SQLAlchemy,
+// `sqlite:///data.db` is relative and `sqlite:////data.db` is absolute
+fn parse_url(url: &str) -> anyhow::Result<(PathBuf, String)> {
+    const USAGE: &str = "expected sqlite:///PATH?table=NAME or sqlite:///PATH?query=SQL";
+    let rest = url.strip_prefix("sqlite:///").context(USAGE)?;
+    let (db, param) = rest.split_once('?').context(USAGE)?;
+    // the query is taken verbatim, so that it need not be URL-encoded
+    let sql = if let Some(sql) = param.strip_prefix("query=") {
+        sql.to_owned()
+    } else if let Some(table) = param.strip_prefix("table=") {
+        format!("SELECT * FROM \"{}\"", table.replace('"', "\"\""))
+    } else {
+        bail!(USAGE);
+    };
+    Ok((PathBuf::from(db), sql))
+}
+
+fn open(db: &Path) -> rusqlite::Result<Connection> {
+    Connection::open_with_flags(
+        db,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+fn render(value: ValueRef) -> Option<String> {
+    Some(match value {
+        ValueRef::Null => return None,
+        ValueRef::Integer(x) => x.to_string(),
+        // as rendered by SQLite itself, e.g. in a CSV export
+        ValueRef::Real(x) if x.fract() == 0.0 && x.abs() < 1e15 => format!("{:.1}", x),
+        ValueRef::Real(x) => x.to_string(),
+        ValueRef::Text(x) => String::from_utf8_lossy(x).into_owned(),
+        ValueRef::Blob(x) => x.iter().fold(String::new(), |mut out, b| {
+            write!(out, "{:02x}", b).unwrap();
+            out
+        }),
+    })
+}
+
+// runs the query, sending the values of the given columns in batches
+fn send_rows(
+    db: &Path,
+    sql: &str,
+    columns: &[usize],
+    tx: &SyncSender<anyhow::Result<Batch>>,
+) -> anyhow::Result<()> {
+    let conn = open(db)?;
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query([])?;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Some(row) = rows.next()? {
+        batch.push(
+            columns
+                .iter()
+                .map(|&idx| row.get_ref(idx).map(render))
+                .collect::<rusqlite::Result<_>>()?,
+        );
+        if batch.len() == BATCH_SIZE {
+            let full = mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if tx.send(Ok(full)).is_err() {
+                return Ok(()); // the reader has been dropped
+            }
+        }
+    }
+    let _ = tx.send(Ok(batch));
+    Ok(())
+}
+
+/// Reads the result of a query, or all rows of a table, of a SQLite database. Column names come
+/// from the statement; the query runs on a separate thread on the first read, once the columns
+/// to be read are known.
+pub struct SqliteReader {
+    db: PathBuf,
+    sql: String,
+    headers: Vec<String>,
+    rx: Option<Receiver<anyhow::Result<Batch>>>,
+    batch: vec::IntoIter<Vec<Option<String>>>,
+}
+
+impl SqliteReader {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let (db, sql) = parse_url(&path.to_string_lossy())?;
+        let conn = open(&db).with_context(|| format!("Failed to open {:?}", db.display()))?;
+        let headers = conn
+            .prepare(&sql)?
+            .column_names()
+            .iter()
+            .map(|x| x.trim().to_owned())
+            .collect();
+        Ok(Self {
+            db,
+            sql,
+            headers,
+            rx: None,
+            batch: Vec::new().into_iter(),
+        })
+    }
+}
+
+impl Reader for SqliteReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        let rx = self.rx.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::sync_channel(1);
+            let (db, sql) = (self.db.clone(), self.sql.clone());
+            let columns = mask.iter_ones().collect::<Vec<_>>();
+            thread::spawn(move || {
+                if let Err(err) = send_rows(&db, &sql, &columns, &tx) {
+                    let _ = tx.send(Err(err));
+                }
+            });
+            rx
+        });
+        let row = loop {
+            if let Some(row) = self.batch.next() {
+                break row;
+            }
+            match rx.recv() {
+                Ok(batch) => self.batch = batch?.into_iter(),
+                Err(_) => return Ok(false), // all rows have been sent
+            }
+        };
+        record.clear();
+        record.extend(row.iter().map(|x| match x {
+            Some(x) => interner.get_or_intern(x.trim()),
+            None => interner.get_or_intern_static(NULL),
+        }));
+        Ok(true)
+    }
+}
@@ -3,3 +3,7 @@ use string_interner::{DefaultBackend, StringInterner};
 
 pub type Interner = StringInterner<DefaultBackend<SymbolUsize>>;
 pub type Sym = SymbolUsize;
+
+/// Interned in place of missing values (e.g. nulls in Parquet files), so that they are never equal
+/// to an empty string. Reports show it as a null rather than as its contents.
+pub const NULL: &str = "\0";
@@ -1,9 +1,13 @@
+use crate::cli::DuplicateKeys;
 use crate::column::Column;
-use crate::sym::{Interner, Sym};
+use crate::config::Config;
+use crate::input::{MalformedRecord, Reader};
+use crate::sym::{Interner, Sym, NULL};
+use anyhow::bail;
 use bitvec::vec::BitVec;
 use byte_slice_cast::AsSliceOf;
-use csv::{ReaderBuilder, StringRecord};
 use indexmap::IndexMap;
+use itertools::{EitherOrBoth, Itertools};
 // use rayon::iter::ParallelIterator; // required for par_values_mut()
 use bitvec::bitvec;
 use bitvec::order::Lsb0;
@@ -11,28 +15,23 @@ use log::info;
 use memmap2::Mmap;
 use std::cmp::Ordering;
 use std::io;
-use std::path::Path;
+use std::ops::Range;
+use string_interner::Symbol;
 
 struct Table(IndexMap<Sym, Column>);
 
 impl Table {
-    fn from_csv<P: AsRef<Path>>(
-        path: P,
-        delimiter: u8,
+    fn from_reader(
+        rdr: &mut dyn Reader,
         columns_to_read: &[Sym],
+        key_columns: &[Sym],
+        null_tokens: &[Sym], // read as nulls in non-key columns, keys are kept distinct
         interner: &mut Interner,
     ) -> anyhow::Result<Self> {
-        info!("reading csv {}", path.as_ref().display());
-
-        let mut rdr = ReaderBuilder::new()
-            //.trim(Trim::All)    // much slower
-            .delimiter(delimiter)
-            .from_path(path.as_ref())?;
-
         let hdrs = rdr
-            .headers()?
+            .headers()
             .iter()
-            .map(|x| interner.get_or_intern(x.trim()))
+            .map(|x| interner.get_or_intern(x))
             .collect::<Vec<Sym>>();
 
         let hdrs_mask = hdrs
@@ -47,17 +46,19 @@ impl Table {
             .map(|(_, s)| Ok((s, Column::new()?)))
             .collect::<io::Result<IndexMap<_, _>>>()?;
 
+        let is_key = m
+            .keys()
+            .map(|x| key_columns.contains(x))
+            .collect::<BitVec>();
+        let null = interner.get_or_intern_static(NULL);
         let mut count = 0usize;
-        let mut record = StringRecord::new();
-        while rdr.read_record(&mut record)? {
-            for (sym, col) in record
-                .iter()
-                .enumerate()
-                .filter(|&(idx, _)| hdrs_mask[idx])
-                .map(|(_, x)| interner.get_or_intern(x.trim()))
-                .zip(m.values_mut())
-            {
-                col.write(sym)?;
+        let mut record = Vec::new();
+        while rdr.read_record(&hdrs_mask, interner, &mut record)? {
+            for ((&sym, col), is_key) in record.iter().zip(m.values_mut()).zip(is_key.iter()) {
+                col.write(match !*is_key && null_tokens.contains(&sym) {
+                    true => null,
+                    false => sym,
+                })?;
             }
             count += 1
         }
@@ -65,7 +66,7 @@ impl Table {
         let mut tbl = Self(m);
         tbl.flush()?;
 
-        info!("read in {} records from {}", count, path.as_ref().display());
+        info!("read in {} records", count);
 
         Ok(tbl)
     }
@@ -90,20 +91,26 @@ pub struct KeyedTable {
     tbl: Table,
     pub key_columns: Vec<Sym>,
     pub non_key_columns: Vec<Sym>,
+    pub source_column: Option<Sym>, // file each row was read from, shown next to the key
+    pub malformed: Vec<MalformedRecord>, // skipped or padded while reading
 }
 
 impl KeyedTable {
-    pub fn from_csv<P: AsRef<Path>>(
-        path: P,
-        delimiter: u8,
+    pub fn from_reader(
+        rdr: &mut dyn Reader,
         columns_to_read: &[Sym],
         key_columns: &[Sym],
+        source_column: Option<Sym>,
+        null_tokens: &[Sym],
         interner: &mut Interner,
     ) -> anyhow::Result<Self> {
-        let tbl = Table::from_csv(path, delimiter, columns_to_read, interner)?;
-        let non_key_columns = tbl
-            .0
-            .keys()
+        let mut read = columns_to_read.to_vec();
+        read.extend(source_column);
+        let tbl = Table::from_reader(rdr, &read, key_columns, null_tokens, interner)?;
+        // take the column order from `columns_to_read` rather than from the file, so that both
+        // tables list their non-key columns in the same order
+        let non_key_columns = columns_to_read
+            .iter()
             .filter(|x| !key_columns.contains(x))
             .copied()
             .collect();
@@ -111,6 +118,8 @@ impl KeyedTable {
             tbl,
             key_columns: key_columns.to_vec(),
             non_key_columns,
+            source_column,
+            malformed: rdr.take_malformed(),
         })
     }
 
@@ -125,16 +134,26 @@ impl KeyedTable {
             &self.non_key_columns
         }
         .iter()
-        .map(|x| {
-            self.tbl
-                .0
-                .get(x)
-                .expect("where'd the columns go?")
-                .get_mmap()
-        })
+        .map(|&x| self.get_col_mmap(x))
         .collect()
     }
 
+    // empty without a source column, so that it can be written like the key columns
+    pub fn get_source_col_mmaps(&self) -> io::Result<Vec<Mmap>> {
+        self.source_column
+            .iter()
+            .map(|&x| self.get_col_mmap(x))
+            .collect()
+    }
+
+    fn get_col_mmap(&self, col: Sym) -> io::Result<Mmap> {
+        self.tbl
+            .0
+            .get(&col)
+            .expect("where'd the columns go?")
+            .get_mmap()
+    }
+
     pub fn sort_by_key_columns(&mut self) -> anyhow::Result<()> {
         let mut indices = (0usize..self.tbl.len()?).collect::<Vec<_>>();
         {
@@ -152,6 +171,34 @@ impl KeyedTable {
             .try_for_each(|col| col.sort_by_indices(&indices))?;
         Ok(())
     }
+
+    // make sure to call this only after sort_by_key_columns
+    // returns (index of first row, row count) for every key that occurs more than once
+    fn find_duplicate_keys(&self) -> anyhow::Result<Vec<(usize, usize)>> {
+        let len = self.len()?;
+        let key_cols_mmaps = self.get_cols_mmaps(true)?;
+        let key_cols_slices = key_cols_mmaps.to_slices()?;
+
+        let mut dups = Vec::new();
+        let mut idx = 0usize;
+        while idx < len {
+            let end = key_group_end(&key_cols_slices, idx, len);
+            if end - idx > 1 {
+                dups.push((idx, end - idx));
+            }
+            idx = end;
+        }
+        Ok(dups)
+    }
+}
+
+// index one past the last row having the same key as the row at `start`
+fn key_group_end(key_cols_slices: &[&[usize]], start: usize, len: usize) -> usize {
+    let mut end = start + 1;
+    while end < len && compare_indices(key_cols_slices, key_cols_slices, start, end).is_eq() {
+        end += 1;
+    }
+    end
 }
 
 fn compare_indices(
@@ -180,6 +227,23 @@ fn compare_indices(
     Ordering::Equal
 }
 
+/// Mmaps of the non-key columns of both tables, paired up by column name.
+///
+/// The columns are listed in the order of `lt.non_key_columns`, irrespective of where they appear
+/// in either file, so the n-th left mmap and the n-th right mmap always belong to the same column.
+pub fn get_aligned_non_key_cols_mmaps(
+    lt: &KeyedTable,
+    rt: &KeyedTable,
+) -> io::Result<[Vec<Mmap>; 2]> {
+    let mut lt_mmaps = Vec::with_capacity(lt.non_key_columns.len());
+    let mut rt_mmaps = Vec::with_capacity(lt.non_key_columns.len());
+    for &col in lt.non_key_columns.iter() {
+        lt_mmaps.push(lt.get_col_mmap(col)?);
+        rt_mmaps.push(rt.get_col_mmap(col)?);
+    }
+    Ok([lt_mmaps, rt_mmaps])
+}
+
 pub trait SlicesFromMmaps {
     fn to_slices(&self) -> Result<Vec<&[usize]>, byte_slice_cast::Error>;
 }
@@ -190,7 +254,11 @@ impl SlicesFromMmaps for Vec<Mmap> {
     }
 }
 
-fn compare_key_cols(lt: &KeyedTable, rt: &KeyedTable) -> anyhow::Result<[Vec<usize>; 4]> {
+fn compare_key_cols(
+    lt: &KeyedTable,
+    rt: &KeyedTable,
+    duplicate_keys: DuplicateKeys,
+) -> anyhow::Result<[Vec<usize>; 4]> {
     info!("comparing key records present in both tables");
     let mut lt_only_indices = Vec::new();
     let mut rt_only_indices = Vec::new();
@@ -207,28 +275,68 @@ fn compare_key_cols(lt: &KeyedTable, rt: &KeyedTable) -> anyhow::Result<[Vec<usi
         let lt_key_cols_slices = lt_key_cols_mmaps.to_slices()?;
         let rt_key_cols_slices = rt_key_cols_mmaps.to_slices()?;
 
+        // only needed to match up rows within a group of duplicate keys
+        let [lt_non_key_cols_mmaps, rt_non_key_cols_mmaps] =
+            get_aligned_non_key_cols_mmaps(lt, rt)?;
+        let lt_non_key_cols_slices = lt_non_key_cols_mmaps.to_slices()?;
+        let rt_non_key_cols_slices = rt_non_key_cols_mmaps.to_slices()?;
+
+        // with `first`, rows after the first one of each key are skipped altogether
+        let next = |slices: &[&[usize]], idx: usize, len: usize| match duplicate_keys {
+            DuplicateKeys::First => key_group_end(slices, idx, len),
+            _ => idx + 1,
+        };
+
         while (lt_idx < lt_len) && (rt_idx < rt_len) {
             match compare_indices(&lt_key_cols_slices, &rt_key_cols_slices, lt_idx, rt_idx) {
                 Ordering::Less => {
                     lt_only_indices.push(lt_idx);
-                    lt_idx += 1;
+                    lt_idx = next(&lt_key_cols_slices, lt_idx, lt_len);
                 }
                 Ordering::Greater => {
                     rt_only_indices.push(rt_idx);
-                    rt_idx += 1;
+                    rt_idx = next(&rt_key_cols_slices, rt_idx, rt_len);
                 }
                 Ordering::Equal => {
-                    lt_common_indices.push(lt_idx);
-                    rt_common_indices.push(rt_idx);
-                    lt_idx += 1;
-                    rt_idx += 1;
+                    let lt_end = key_group_end(&lt_key_cols_slices, lt_idx, lt_len);
+                    let rt_end = key_group_end(&rt_key_cols_slices, rt_idx, rt_len);
+                    if duplicate_keys == DuplicateKeys::Multiset
+                        && (lt_end - lt_idx > 1 || rt_end - rt_idx > 1)
+                    {
+                        let [lt_only, lt_common, rt_common, rt_only] = match_rows_as_multiset(
+                            (&lt_non_key_cols_slices, &rt_non_key_cols_slices),
+                            lt_idx..lt_end,
+                            rt_idx..rt_end,
+                        );
+                        lt_only_indices.extend(lt_only);
+                        lt_common_indices.extend(lt_common);
+                        rt_common_indices.extend(rt_common);
+                        rt_only_indices.extend(rt_only);
+                    } else {
+                        lt_common_indices.push(lt_idx);
+                        rt_common_indices.push(rt_idx);
+                    }
+                    lt_idx = lt_end;
+                    rt_idx = rt_end;
                 }
             }
         }
+
+        while lt_idx < lt_len {
+            lt_only_indices.push(lt_idx);
+            lt_idx = next(&lt_key_cols_slices, lt_idx, lt_len);
+        }
+        while rt_idx < rt_len {
+            rt_only_indices.push(rt_idx);
+            rt_idx = next(&rt_key_cols_slices, rt_idx, rt_len);
+        }
     }
 
-    lt_only_indices.extend(lt_idx..lt_len);
-    rt_only_indices.extend(rt_idx..rt_len);
+    if duplicate_keys == DuplicateKeys::Multiset {
+        // unmatched rows of duplicate keys are appended out of order
+        lt_only_indices.sort_unstable();
+        rt_only_indices.sort_unstable();
+    }
 
     debug_assert_eq!(lt_common_indices.len(), rt_common_indices.len());
 
@@ -240,35 +348,177 @@ fn compare_key_cols(lt: &KeyedTable, rt: &KeyedTable) -> anyhow::Result<[Vec<usi
     ])
 }
 
+// Pairs up the rows of a group of duplicate keys. Rows with identical values on both sides are
+// paired first, the rest are paired in order and whatever remains is only present on one side.
+fn match_rows_as_multiset(
+    (lt_slices, rt_slices): (&[&[usize]], &[&[usize]]),
+    lt_range: Range<usize>,
+    rt_range: Range<usize>,
+) -> [Vec<usize>; 4] {
+    let mut lt_rows = lt_range.collect::<Vec<_>>();
+    let mut rt_rows = rt_range.collect::<Vec<_>>();
+    lt_rows.sort_by(|&idx1, &idx2| compare_indices(lt_slices, lt_slices, idx1, idx2));
+    rt_rows.sort_by(|&idx1, &idx2| compare_indices(rt_slices, rt_slices, idx1, idx2));
+
+    let (mut lt_common, mut rt_common) = (Vec::new(), Vec::new());
+    let (mut lt_unmatched, mut rt_unmatched) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0usize, 0usize);
+    while i < lt_rows.len() && j < rt_rows.len() {
+        match compare_indices(lt_slices, rt_slices, lt_rows[i], rt_rows[j]) {
+            Ordering::Less => {
+                lt_unmatched.push(lt_rows[i]);
+                i += 1;
+            }
+            Ordering::Greater => {
+                rt_unmatched.push(rt_rows[j]);
+                j += 1;
+            }
+            Ordering::Equal => {
+                lt_common.push(lt_rows[i]);
+                rt_common.push(rt_rows[j]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    lt_unmatched.extend_from_slice(&lt_rows[i..]);
+    rt_unmatched.extend_from_slice(&rt_rows[j..]);
+
+    let paired = lt_unmatched.len().min(rt_unmatched.len());
+    lt_common.extend(lt_unmatched.drain(..paired));
+    rt_common.extend(rt_unmatched.drain(..paired));
+
+    [lt_unmatched, lt_common, rt_common, rt_unmatched]
+}
+
+// merges the duplicate keys found in both (sorted) tables into a single list ordered by key
+fn merge_duplicate_keys(
+    lt: &KeyedTable,
+    rt: &KeyedTable,
+    lt_dups: Vec<(usize, usize)>,
+    rt_dups: Vec<(usize, usize)>,
+) -> anyhow::Result<Vec<DuplicateKey>> {
+    let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
+    let rt_key_cols_mmaps = rt.get_cols_mmaps(true)?;
+    let lt_key_cols_slices = lt_key_cols_mmaps.to_slices()?;
+    let rt_key_cols_slices = rt_key_cols_mmaps.to_slices()?;
+
+    // number of rows in the other table having the same key as `idx`
+    let count_in = |slices: &[&[usize]], other_slices: &[&[usize]], other_len, idx| {
+        let (mut lo, mut hi) = (0usize, other_len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if compare_indices(other_slices, slices, mid, idx).is_lt() {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo..other_len)
+            .take_while(|&x| compare_indices(other_slices, slices, x, idx).is_eq())
+            .count()
+    };
+
+    let (lt_len, rt_len) = (lt.len()?, rt.len()?);
+    Ok(lt_dups
+        .into_iter()
+        .merge_join_by(rt_dups, |&(lt_idx, _), &(rt_idx, _)| {
+            compare_indices(&lt_key_cols_slices, &rt_key_cols_slices, lt_idx, rt_idx)
+        })
+        .map(|x| match x {
+            EitherOrBoth::Both((lt_idx, lt_count), (rt_idx, rt_count)) => DuplicateKey {
+                indices: [Some(lt_idx), Some(rt_idx)],
+                counts: [lt_count, rt_count],
+            },
+            EitherOrBoth::Left((lt_idx, lt_count)) => DuplicateKey {
+                indices: [Some(lt_idx), None],
+                counts: [
+                    lt_count,
+                    count_in(&lt_key_cols_slices, &rt_key_cols_slices, rt_len, lt_idx),
+                ],
+            },
+            EitherOrBoth::Right((rt_idx, rt_count)) => DuplicateKey {
+                indices: [None, Some(rt_idx)],
+                counts: [
+                    count_in(&rt_key_cols_slices, &lt_key_cols_slices, lt_len, rt_idx),
+                    rt_count,
+                ],
+            },
+        })
+        .collect())
+}
+
+/// A key occurring more than once in at least one of the tables.
+pub struct DuplicateKey {
+    pub indices: [Option<usize>; 2], // index of the first row with this key in either table
+    pub counts: [usize; 2],          // number of rows with this key in either table
+}
+
+// string value of a symbol read from a column slice
+pub fn resolve(interner: &Interner, value: usize) -> &str {
+    interner
+        .resolve(Sym::try_from_usize(value).unwrap())
+        .unwrap()
+}
+
 pub struct Comparison {
     pub tt: Vec<BitVec>,
+    pub duplicate_keys: Vec<DuplicateKey>,
     pub only_indices: [Vec<usize>; 2], // indices of rows that are only present on left and right tables
     pub common_indices: [Vec<usize>; 2], // indices of rows that are present on both sides
     pub diff_row_count: usize,
     pub diff_cell_count: usize,
+    pub null_diff_cell_count: usize, // differing cells with a null on only one side
     pub match_row_count: usize,
     pub match_cell_count: usize,
 }
 
-pub fn compare_tables(lt: &KeyedTable, rt: &KeyedTable) -> anyhow::Result<Comparison> {
+pub fn compare_tables(
+    lt: &KeyedTable,
+    rt: &KeyedTable,
+    config: &Config,
+    interner: &Interner,
+) -> anyhow::Result<Comparison> {
     info!("starting table comparison");
+    let duplicate_keys = config.duplicate_keys;
+    let (lt_dups, rt_dups) = (lt.find_duplicate_keys()?, rt.find_duplicate_keys()?);
+    if !lt_dups.is_empty() || !rt_dups.is_empty() {
+        info!(
+            "found {} duplicate keys in left table and {} in right table",
+            lt_dups.len(),
+            rt_dups.len()
+        );
+        if duplicate_keys == DuplicateKeys::Error {
+            bail!(
+                "found {} duplicate keys in left file and {} in right file \
+                 (use --on-duplicate-keys to compare them anyway)",
+                lt_dups.len(),
+                rt_dups.len()
+            );
+        }
+    }
+
     let [lt_only_indices, mut lt_common_indices, mut rt_common_indices, rt_only_indices] =
-        compare_key_cols(lt, rt)?;
+        compare_key_cols(lt, rt, duplicate_keys)?;
 
     let mut diff_cell_count = 0;
+    let mut null_diff_cell_count = 0;
+    let null = interner.get(NULL).map(|x| x.to_usize());
     let mut match_cell_count = lt_common_indices.len() * lt.key_columns.len();
 
     let mut tt = {
-        let lt_non_key_cols_mmaps = lt.get_cols_mmaps(false)?;
-        let rt_non_key_cols_mmaps = rt.get_cols_mmaps(false)?;
+        let [lt_non_key_cols_mmaps, rt_non_key_cols_mmaps] =
+            get_aligned_non_key_cols_mmaps(lt, rt)?;
 
         let lt_non_key_cols_slices = lt_non_key_cols_mmaps.to_slices()?;
         let rt_non_key_cols_slices = rt_non_key_cols_mmaps.to_slices()?;
 
         lt_non_key_cols_slices
             .into_iter()
-            .zip(rt_non_key_cols_slices.into_iter())
-            .map(|(lt_col, rt_col)| {
+            .zip(rt_non_key_cols_slices)
+            .zip(lt.non_key_columns.iter())
+            .map(|((lt_col, rt_col), col)| {
+                let comparator = config.comparators.get(col);
                 let filtered_lt_col = lt_common_indices
                     .iter()
                     .map(|&idx| unsafe { lt_col.get_unchecked(idx) });
@@ -279,11 +529,17 @@ pub fn compare_tables(lt: &KeyedTable, rt: &KeyedTable) -> anyhow::Result<Compar
                 filtered_lt_col
                     .zip(filtered_rt_col)
                     .map(|(&lt_val, &rt_val)| {
-                        let equal = lt_val == rt_val;
+                        let equal = lt_val == rt_val
+                            || comparator.is_some_and(|c| {
+                                c.equal(resolve(interner, lt_val), resolve(interner, rt_val))
+                            });
                         if equal {
                             match_cell_count += 1;
                         } else {
                             diff_cell_count += 1;
+                            if null == Some(lt_val) || null == Some(rt_val) {
+                                null_diff_cell_count += 1;
+                            }
                         }
                         equal
                     })
@@ -324,10 +580,12 @@ pub fn compare_tables(lt: &KeyedTable, rt: &KeyedTable) -> anyhow::Result<Compar
 
     Ok(Comparison {
         tt,
+        duplicate_keys: merge_duplicate_keys(lt, rt, lt_dups, rt_dups)?,
         only_indices: [lt_only_indices, rt_only_indices],
         common_indices: [lt_common_indices, rt_common_indices],
         diff_row_count,
         diff_cell_count,
+        null_diff_cell_count,
         match_row_count,
         match_cell_count,
     })
@@ -0,0 +1,281 @@
+use crate::sym::{Sym, NULL};
+use crate::table::{get_aligned_non_key_cols_mmaps, resolve, Comparison, SlicesFromMmaps};
+use crate::{Config, Interner, KeyedTable};
+use log::info;
+use std::borrow::Cow;
+use std::env;
+use std::io::{self, BufWriter, Write};
+use terminal_size::{terminal_size, Width};
+
+const MAX_CELL_WIDTH: usize = 40;
+const DEFAULT_TERM_WIDTH: usize = 80;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+fn term_width() -> usize {
+    match terminal_size() {
+        Some((Width(w), _)) => w as usize,
+        None => env::var("COLUMNS")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_TERM_WIDTH),
+    }
+}
+
+fn width(x: &str) -> usize {
+    x.chars().count().min(MAX_CELL_WIDTH)
+}
+
+// writes `x` left aligned in `width` characters, truncating it if needed
+fn write_cell<W: Write>(out: &mut W, x: &str, width: usize, style: Option<&str>) -> io::Result<()> {
+    let len = x.chars().count();
+    if let Some(style) = style {
+        write!(out, "{}", style)?;
+    }
+    if len > width {
+        let end = x.char_indices().nth(width - 1).map_or(x.len(), |(i, _)| i);
+        write!(out, "{}…", &x[..end])?;
+    } else {
+        write!(out, "{}{:2$}", x, "", width - len)?;
+    }
+    if style.is_some() {
+        write!(out, "{}", RESET)?;
+    }
+    Ok(())
+}
+
+// `x` to be written in `width` characters, given that its first `prefix` characters are the same
+// as those of the value it differs from. If that difference would be cut off, the start of `x` is
+// cut instead, showing the difference in the middle of the cell.
+fn from_diff(x: &str, prefix: usize, width: usize) -> Cow<'_, str> {
+    if width < 4 || prefix + 1 < width || x.chars().count() <= width {
+        return Cow::Borrowed(x);
+    }
+    let skip = prefix + 1 - width / 2;
+    let start = x.char_indices().nth(skip).map_or(x.len(), |(i, _)| i);
+    Cow::Owned(format!("…{}", &x[start..]))
+}
+
+fn write_stats<W: Write>(
+    out: &mut W,
+    comparison: &Comparison,
+    malformed_count: usize,
+    color: bool,
+) -> io::Result<()> {
+    let style = |x| if color { Some(x) } else { None };
+    let mut write_stat = |name, rows, suffix: String, highlight| -> io::Result<()> {
+        write_cell(out, name, 15, style(BOLD))?;
+        match style(highlight) {
+            Some(highlight) if rows > 0 => write!(out, "{}{} rows{}", highlight, rows, RESET)?,
+            _ => write!(out, "{} rows", rows)?,
+        }
+        writeln!(out, "{}", suffix)
+    };
+    write_stat("Matched", comparison.match_row_count, String::new(), GREEN)?;
+    write_stat(
+        "Diffs",
+        comparison.diff_row_count,
+        match comparison.null_diff_cell_count {
+            0 => format!(" ({} cells)", comparison.diff_cell_count),
+            n => format!(
+                " ({} cells, of which {} null vs value)",
+                comparison.diff_cell_count, n
+            ),
+        },
+        RED,
+    )?;
+    write_stat(
+        "Only in left",
+        comparison.only_indices[0].len(),
+        String::new(),
+        RED,
+    )?;
+    write_stat(
+        "Only in right",
+        comparison.only_indices[1].len(),
+        String::new(),
+        GREEN,
+    )?;
+    if !comparison.duplicate_keys.is_empty() {
+        write_stat(
+            "Duplicate keys",
+            comparison.duplicate_keys.len(),
+            String::new(),
+            RED,
+        )?;
+    }
+    if malformed_count > 0 {
+        write_stat("Malformed", malformed_count, String::new(), RED)?;
+    }
+    Ok(())
+}
+
+// greedily packs columns of the given widths into chunks fitting in `avail` characters, returns
+// the ranges of column positions of each chunk
+fn chunk_columns(widths: &[usize], avail: usize) -> Vec<(usize, usize)> {
+    let mut chunks = Vec::new();
+    let (mut start, mut used) = (0usize, 0usize);
+    for (idx, &w) in widths.iter().enumerate() {
+        if idx > start && used + w > avail {
+            chunks.push((start, idx));
+            start = idx;
+            used = 0;
+        }
+        used += w;
+    }
+    if start < widths.len() {
+        chunks.push((start, widths.len()));
+    }
+    chunks
+}
+
+/// Prints the comparison stats followed by the differing rows, with the left and right values of
+/// each differing column side by side. Only columns having at least one difference are shown, and
+/// if they do not fit in the terminal they are split into several tables, each starting with the
+/// key columns.
+pub fn write_term_diff<W: Write>(
+    out: &mut W,
+    config: &Config,
+    (lt, rt): (&KeyedTable, &KeyedTable),
+    interner: &Interner,
+    comparison: &Comparison,
+    color: bool,
+) -> anyhow::Result<()> {
+    let mut out = BufWriter::new(out);
+    info!("generating comparison for terminal");
+    let style = |x| if color { Some(x) } else { None };
+
+    write_stats(
+        &mut out,
+        comparison,
+        lt.malformed.len() + rt.malformed.len(),
+        color,
+    )?;
+
+    if comparison.common_indices[0].is_empty() {
+        out.flush()?;
+        return Ok(());
+    }
+
+    let lt_key_cols_mmaps = lt.get_cols_mmaps(true)?;
+    let lt_key_cols_slices = lt_key_cols_mmaps.to_slices()?;
+
+    let [lt_non_key_cols_mmaps, rt_non_key_cols_mmaps] = get_aligned_non_key_cols_mmaps(lt, rt)?;
+    let lt_non_key_cols_slices = lt_non_key_cols_mmaps.to_slices()?;
+    let rt_non_key_cols_slices = rt_non_key_cols_mmaps.to_slices()?;
+
+    // positions of the non-key columns having at least one diff
+    let diff_cols = (0..lt.non_key_columns.len())
+        .filter(|&c| comparison.tt[c].not_all())
+        .collect::<Vec<_>>();
+
+    let value =
+        |slice: &[usize], idx: usize| match resolve(interner, unsafe { *slice.get_unchecked(idx) })
+        {
+            NULL => "null",
+            x => x,
+        };
+    let name = |col: Sym| config.col_name(col, interner);
+
+    let rows = comparison.common_indices[0]
+        .iter()
+        .zip(comparison.common_indices[1].iter());
+
+    let mut key_widths = lt
+        .key_columns
+        .iter()
+        .map(|&c| width(name(c)))
+        .collect::<Vec<_>>();
+    // each differing column is shown as a pair of left and right values
+    let mut diff_col_widths = vec![(0usize, 0usize); diff_cols.len()];
+    for (&lt_idx, &rt_idx) in rows.clone() {
+        for (w, slice) in key_widths.iter_mut().zip(lt_key_cols_slices.iter()) {
+            *w = (*w).max(width(value(slice, lt_idx)));
+        }
+        for (w, &c) in diff_col_widths.iter_mut().zip(diff_cols.iter()) {
+            w.0 = w.0.max(width(value(lt_non_key_cols_slices[c], lt_idx)));
+            w.1 = w.1.max(width(value(rt_non_key_cols_slices[c], rt_idx)));
+        }
+    }
+    for (w, &c) in diff_col_widths.iter_mut().zip(diff_cols.iter()) {
+        // the column name spans both values
+        let name_width = width(name(lt.non_key_columns[c]));
+        if w.0 + 1 + w.1 < name_width {
+            w.1 = name_width - w.0 - 1;
+        }
+    }
+
+    // separator between columns, and between the left and right values of a column
+    const SEP: &str = " │ ";
+    let value_sep = if color { " " } else { "|" };
+    let keys_width = key_widths.iter().map(|w| w + 1).sum::<usize>();
+    let chunks = chunk_columns(
+        &diff_col_widths
+            .iter()
+            .map(|(l, r)| SEP.chars().count() + l + 1 + r)
+            .collect::<Vec<_>>(),
+        term_width().saturating_sub(keys_width),
+    );
+
+    for (start, end) in chunks {
+        writeln!(out)?;
+
+        // header
+        for (&c, &w) in lt.key_columns.iter().zip(key_widths.iter()) {
+            write_cell(&mut out, name(c), w, style(BOLD))?;
+            write!(out, " ")?;
+        }
+        for (&c, &(l, r)) in diff_cols[start..end]
+            .iter()
+            .zip(diff_col_widths[start..end].iter())
+        {
+            write!(out, "{}", SEP)?;
+            write_cell(
+                &mut out,
+                name(lt.non_key_columns[c]),
+                l + 1 + r,
+                style(BOLD),
+            )?;
+        }
+        writeln!(out)?;
+
+        for (idx, (&lt_idx, &rt_idx)) in rows.clone().enumerate() {
+            // skip rows without diffs in this chunk
+            if diff_cols[start..end].iter().all(|&c| comparison.tt[c][idx]) {
+                continue;
+            }
+            for (slice, &w) in lt_key_cols_slices.iter().zip(key_widths.iter()) {
+                write_cell(&mut out, value(slice, lt_idx), w, None)?;
+                write!(out, " ")?;
+            }
+            for (&c, &(l, r)) in diff_cols[start..end]
+                .iter()
+                .zip(diff_col_widths[start..end].iter())
+            {
+                write!(out, "{}", SEP)?;
+                let lt_val = value(lt_non_key_cols_slices[c], lt_idx);
+                if comparison.tt[c][idx] {
+                    write_cell(&mut out, lt_val, l + 1 + r, style(DIM))?;
+                } else {
+                    let rt_val = value(rt_non_key_cols_slices[c], rt_idx);
+                    let prefix = lt_val
+                        .chars()
+                        .zip(rt_val.chars())
+                        .take_while(|(x, y)| x == y)
+                        .count();
+                    write_cell(&mut out, &from_diff(lt_val, prefix, l), l, style(RED))?;
+                    write!(out, "{}", value_sep)?;
+                    write_cell(&mut out, &from_diff(rt_val, prefix, r), r, style(GREEN))?;
+                }
+            }
+            writeln!(out)?;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use crate::table::Comparison;
+use crate::{Config, KeyedTable};
+use clap::ValueEnum;
+use std::fmt;
+use std::io;
+
+/// Counter of a comparison that thresholds can be set on.
+#[derive(Clone, Copy, ValueEnum)]
+#[clap(rename_all = "snake_case")]
+pub enum Metric {
+    MatchRows,
+    DiffRows,
+    MatchCells,
+    DiffCells,
+    /// Differing cells with a null (see `--null-tokens`) on only one side
+    NullDiffCells,
+    /// Differing cells with values on both sides
+    ValueDiffCells,
+    LeftOnlyRows,
+    RightOnlyRows,
+    DuplicateKeys,
+    /// Records of both files that were skipped or padded, see `--on-malformed`
+    MalformedRecords,
+    /// Percentage of cells that differ, as shown in the HTML report
+    DiffCellPct,
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+// longer operators first so that `>=` is not taken to be `>`
+const OPS: [(&str, Op); 6] = [
+    (">=", Op::Ge),
+    ("<=", Op::Le),
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    (">", Op::Gt),
+    ("<", Op::Lt),
+];
+
+/// A condition on a comparison counter, e.g. `diff_rows>0`, that fails the run when true.
+#[derive(Clone)]
+pub struct Threshold {
+    expr: String,
+    metric: Metric,
+    op: Op,
+    value: f64,
+}
+
+impl Threshold {
+    pub fn new(metric: Metric, op_str: &str, value: f64) -> Self {
+        let &(_, op) = OPS
+            .iter()
+            .find(|&&(x, _)| x == op_str)
+            .expect("unknown operator");
+        Self {
+            expr: format!(
+                "{}{}{}",
+                metric.to_possible_value().unwrap().get_name(),
+                op_str,
+                value
+            ),
+            metric,
+            op,
+            value,
+        }
+    }
+
+    pub fn parse(x: &str) -> Result<Self, String> {
+        let (pos, op_str, op) = OPS
+            .iter()
+            .filter_map(|&(op_str, op)| x.find(op_str).map(|pos| (pos, op_str, op)))
+            .min_by_key(|&(pos, _, _)| pos)
+            .ok_or("threshold should be of the form METRIC<OP>VALUE, e.g. diff_rows>0")?;
+        let metric = Metric::from_str(x[..pos].trim(), true).map_err(|_| {
+            format!(
+                "unknown metric {:?}, expected one of: {}",
+                x[..pos].trim(),
+                Metric::value_variants()
+                    .iter()
+                    .map(|x| x.to_possible_value().unwrap().get_name().to_owned())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+        let value = x[pos + op_str.len()..]
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| format!("invalid threshold value: {}", e))?;
+        Ok(Self {
+            expr: x.trim().to_owned(),
+            metric,
+            op,
+            value,
+        })
+    }
+
+    pub fn is_breached(
+        &self,
+        config: &Config,
+        (lt, rt): (&KeyedTable, &KeyedTable),
+        comparison: &Comparison,
+    ) -> io::Result<bool> {
+        let actual = match self.metric {
+            Metric::MatchRows => comparison.match_row_count as f64,
+            Metric::DiffRows => comparison.diff_row_count as f64,
+            Metric::MatchCells => comparison.match_cell_count as f64,
+            Metric::DiffCells => comparison.diff_cell_count as f64,
+            Metric::NullDiffCells => comparison.null_diff_cell_count as f64,
+            Metric::ValueDiffCells => {
+                (comparison.diff_cell_count - comparison.null_diff_cell_count) as f64
+            }
+            Metric::LeftOnlyRows => comparison.only_indices[0].len() as f64,
+            Metric::RightOnlyRows => comparison.only_indices[1].len() as f64,
+            Metric::DuplicateKeys => comparison.duplicate_keys.len() as f64,
+            Metric::MalformedRecords => (lt.malformed.len() + rt.malformed.len()) as f64,
+            Metric::DiffCellPct => {
+                let lt_col_count =
+                    lt.key_columns.len() + lt.non_key_columns.len() + config.ignored_cols[0].len();
+                let rt_col_count =
+                    rt.key_columns.len() + rt.non_key_columns.len() + config.ignored_cols[1].len();
+                let total_cell_count = lt.len()? * lt_col_count + rt.len()? * rt_col_count;
+                if total_cell_count == 0 {
+                    0.0
+                } else {
+                    (2 * 100 * comparison.diff_cell_count) as f64 / total_cell_count as f64
+                }
+            }
+        };
+        Ok(match self.op {
+            Op::Gt => actual > self.value,
+            Op::Ge => actual >= self.value,
+            Op::Lt => actual < self.value,
+            Op::Le => actual <= self.value,
+            Op::Eq => actual == self.value,
+            Op::Ne => actual != self.value,
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
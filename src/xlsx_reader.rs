@@ -0,0 +1,144 @@
+use crate::input::Reader;
+use crate::sym::{Interner, Sym};
+use anyhow::{bail, Context};
+use bitvec::vec::BitVec;
+use calamine::{open_workbook_auto, Data, Range, Reader as _};
+use std::fmt::Write;
+use std::path::Path;
+
+/// How cell values of a spreadsheet are rendered as strings.
+#[derive(Clone)]
+pub struct CellFormat {
+    pub date_format: String,     // for dates without a time of day
+    pub datetime_format: String, // for other dates
+    pub decimals: Option<usize>, // for non-integer numbers, shortest representation if not given
+}
+
+fn write_cell(out: &mut String, cell: &Data, format: &CellFormat) -> anyhow::Result<()> {
+    match cell {
+        Data::Empty => (),
+        Data::String(x) | Data::DateTimeIso(x) | Data::DurationIso(x) => out.push_str(x),
+        Data::Int(x) => write!(out, "{}", x)?,
+        Data::Float(x) => match format.decimals {
+            Some(decimals) if x.fract() != 0.0 => write!(out, "{:.*}", decimals, x)?,
+            _ => write!(out, "{}", x)?,
+        },
+        Data::Bool(x) => write!(out, "{}", x)?,
+        Data::DateTime(x) if x.is_duration() => {
+            let secs = x.as_duration().unwrap_or_default().num_seconds();
+            write!(
+                out,
+                "{}:{:02}:{:02}",
+                secs / 3600,
+                secs % 3600 / 60,
+                secs % 60
+            )?;
+        }
+        Data::DateTime(x) => match x.as_datetime() {
+            Some(dt) if dt.time() == chrono::NaiveTime::MIN => {
+                write!(out, "{}", dt.format(&format.date_format))?
+            }
+            Some(dt) => write!(out, "{}", dt.format(&format.datetime_format))?,
+            None => write!(out, "{}", x.as_f64())?,
+        },
+        Data::Error(x) => write!(out, "{}", x)?,
+    }
+    Ok(())
+}
+
+/// Reads a worksheet of an Excel (xlsx, xlsm, xlsb, xls) or OpenDocument (ods) workbook. Rows
+/// before the header row are skipped, as are rows with all cells empty. Empty cells are read as
+/// empty strings, as they appear in a CSV export of the sheet.
+pub struct XlsxReader {
+    range: Range<Data>,
+    headers: Vec<String>,
+    format: CellFormat,
+    row: usize, // next row to be read, relative to the start of `range`
+    buf: String,
+}
+
+impl XlsxReader {
+    /// `sheet` defaults to the first sheet of the workbook, `header_row` is 1-based as in the
+    /// spreadsheet.
+    pub fn new(
+        path: &Path,
+        sheet: Option<&str>,
+        header_row: usize,
+        format: &CellFormat,
+    ) -> anyhow::Result<Self> {
+        let mut workbook = open_workbook_auto(path)?;
+        let range = match sheet {
+            Some(sheet) => workbook
+                .worksheet_range(sheet)
+                .with_context(|| format!("Failed to read sheet {:?}", sheet))?,
+            None => match workbook.worksheet_range_at(0) {
+                Some(range) => range?,
+                None => bail!("workbook has no sheets"),
+            },
+        };
+
+        // the range starts at the first non-empty cell of the sheet
+        let (start_row, _) = range.start().unwrap_or_default();
+        let header_idx = match header_row.checked_sub(1 + start_row as usize) {
+            Some(idx) if idx < range.height() => idx,
+            _ => bail!("header row {} of the sheet is empty", header_row),
+        };
+
+        let mut buf = String::new();
+        let headers = (0..range.width())
+            .map(|col| {
+                buf.clear();
+                if let Some(cell) = range.get((header_idx, col)) {
+                    write_cell(&mut buf, cell, format)?;
+                }
+                Ok(buf.trim().to_owned())
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            range,
+            headers,
+            format: format.clone(),
+            row: header_idx + 1,
+            buf,
+        })
+    }
+}
+
+impl Reader for XlsxReader {
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    fn read_record(
+        &mut self,
+        mask: &BitVec,
+        interner: &mut Interner,
+        record: &mut Vec<Sym>,
+    ) -> anyhow::Result<bool> {
+        // cells are indexed directly, as `Range::rows().nth` walks the rows from the start
+        let row = loop {
+            if self.row >= self.range.height() {
+                return Ok(false);
+            }
+            self.row += 1;
+            let row = self.row - 1;
+            if (0..self.range.width()).any(|col| {
+                self.range
+                    .get((row, col))
+                    .is_some_and(|x| *x != Data::Empty)
+            }) {
+                break row;
+            }
+        };
+        record.clear();
+        for col in mask.iter_ones() {
+            self.buf.clear();
+            if let Some(cell) = self.range.get((row, col)) {
+                write_cell(&mut self.buf, cell, &self.format)?;
+            }
+            record.push(interner.get_or_intern(self.buf.trim()));
+        }
+        Ok(true)
+    }
+}